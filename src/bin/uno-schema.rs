@@ -0,0 +1,7 @@
+use uno_server::packets::schema;
+
+// Prints the JSON schema of the protocol, regenerate 'schema/protocol.json' with
+// cargo run --bin uno-schema > schema/protocol.json
+fn main() {
+    println!("{}", serde_json::to_string_pretty(&schema()).unwrap());
+}
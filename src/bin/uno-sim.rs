@@ -0,0 +1,242 @@
+use clap::{Arg, Command, ErrorKind};
+use rand::rngs::StdRng;
+use rand::{random, SeedableRng};
+use serde::Serialize;
+use uno_server::bot::BotStrategy;
+use uno_server::game::{Action, Game};
+use uno_server::options::GameOptions;
+use uno_server::packets::PacketType;
+use uuid::Uuid;
+
+// Actions allowed per turn on average before a game is counted as a stalemate
+const ACTIONS_PER_TURN: usize = 64;
+
+#[derive(Debug, Default, Serialize)]
+struct SeatReport {
+    seat: usize,
+    strategy: String,
+    wins: usize,
+    win_rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    seed: u64,
+    games: usize,
+    avg_turns: f64,
+    avg_cards_drawn: f64,
+    avg_reshuffles: f64,
+    stalemates: usize,
+    seats: Vec<SeatReport>,
+}
+
+// Result of a single simulated game
+struct Outcome {
+    winner: Option<usize>,
+    turns: usize,
+    cards_drawn: usize,
+    reshuffles: usize,
+    stalemate: bool,
+}
+
+// Plays a full game between the given strategies, every seat is driven through 'Game::handle'
+fn simulate(
+    seed: u64,
+    strategies: &[BotStrategy],
+    options: &GameOptions,
+    max_turns: usize,
+) -> Outcome {
+    let mut game = Game::with_seed(seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let seats: Vec<Uuid> = (0..strategies.len()).map(|_| Uuid::new_v4()).collect();
+    let mut bots: Vec<_> = strategies.iter().map(|s| s.create()).collect();
+
+    for (i, id) in seats.iter().enumerate() {
+        game.join(*id);
+        game.register(*id, &format!("seat {}", i));
+    }
+    game.handle(seats[0], Action::StartGame(options.clone()));
+
+    let mut winner = None;
+    let mut actions = 0;
+
+    while game.active && game.turn <= max_turns && actions < max_turns * ACTIONS_PER_TURN {
+        let id = game.current_turn.unwrap();
+        let seat = seats.iter().position(|s| *s == id).unwrap();
+
+        let action = bots[seat].decide(&game.bot_view(&id), &mut rng);
+        actions += 1;
+
+        for event in game.handle(id, action) {
+            match event.packet {
+                PacketType::WinUpdate { id, .. } if options.target_score.is_none() => {
+                    winner = Some(id)
+                }
+                PacketType::MatchUpdate {
+                    winner: Some(id), ..
+                } => winner = Some(id),
+                _ => {}
+            }
+        }
+    }
+
+    Outcome {
+        winner: winner.and_then(|id| seats.iter().position(|s| *s == id)),
+        turns: game.turn,
+        cards_drawn: game.statistics.cards_drawn,
+        reshuffles: game.statistics.reshuffles,
+        stalemate: game.active,
+    }
+}
+
+fn to_csv(report: &Report) -> String {
+    let mut csv = String::from(
+        "seat,strategy,games,wins,win_rate,avg_turns,avg_cards_drawn,avg_reshuffles,stalemates,seed\n",
+    );
+
+    for seat in &report.seats {
+        csv.push_str(&format!(
+            "{},{},{},{},{:.4},{:.2},{:.2},{:.2},{},{}\n",
+            seat.seat,
+            seat.strategy,
+            report.games,
+            seat.wins,
+            seat.win_rate,
+            report.avg_turns,
+            report.avg_cards_drawn,
+            report.avg_reshuffles,
+            report.stalemates,
+            report.seed
+        ));
+    }
+
+    csv
+}
+
+fn main() {
+    let mut command = Command::new("uno-sim")
+        .about("Runs games between bots without networking and reports aggregate statistics")
+        .arg(
+            Arg::new("games")
+                .short('n')
+                .long("games")
+                .help("Amount of games to simulate")
+                .takes_value(true)
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("bots")
+                .short('b')
+                .long("bots")
+                .help("Comma separated strategies in seat order: random, greedy or color")
+                .takes_value(true)
+                .default_value("random,greedy,color"),
+        )
+        .arg(
+            Arg::new("options")
+                .short('o')
+                .long("options")
+                .help("House rules as sent with the 'StartGame' packet")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("seed")
+                .short('s')
+                .long("seed")
+                .help("Seed of the first game, following games use the next seeds")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .help("Output format")
+                .takes_value(true)
+                .possible_values(["csv", "json"])
+                .default_value("csv"),
+        )
+        .arg(
+            Arg::new("max-turns")
+                .long("max-turns")
+                .help("Turns after which a game is counted as a stalemate")
+                .takes_value(true)
+                .default_value("5000"),
+        );
+    let matches = command.get_matches_mut();
+
+    let games: usize = matches.value_of_t_or_exit("games");
+    let max_turns: usize = matches.value_of_t_or_exit("max-turns");
+    let seed: u64 = if matches.is_present("seed") {
+        matches.value_of_t_or_exit("seed")
+    } else {
+        random()
+    };
+
+    let strategies: Vec<BotStrategy> = matches
+        .value_of("bots")
+        .unwrap()
+        .split(',')
+        .map(|s| s.trim().parse())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| command.error(ErrorKind::InvalidValue, e).exit());
+
+    let options = GameOptions::parse(matches.value_of("options").unwrap_or_default())
+        .unwrap_or_else(|e| command.error(ErrorKind::InvalidValue, e).exit());
+
+    if strategies.len() < 2 {
+        command
+            .error(ErrorKind::InvalidValue, "At least two bots are needed")
+            .exit();
+    }
+    if let Err(e) = options.validate(strategies.len(), Game::with_seed(seed).deck.len()) {
+        command.error(ErrorKind::InvalidValue, e).exit();
+    }
+
+    let mut seats: Vec<SeatReport> = strategies
+        .iter()
+        .enumerate()
+        .map(|(seat, strategy)| SeatReport {
+            seat,
+            strategy: strategy.to_string(),
+            ..SeatReport::default()
+        })
+        .collect();
+    let (mut turns, mut cards_drawn, mut reshuffles, mut stalemates) = (0, 0, 0, 0);
+
+    for i in 0..games {
+        let outcome = simulate(
+            seed.wrapping_add(i as u64),
+            &strategies,
+            &options,
+            max_turns,
+        );
+
+        if let Some(winner) = outcome.winner {
+            seats[winner].wins += 1;
+        }
+        turns += outcome.turns;
+        cards_drawn += outcome.cards_drawn;
+        reshuffles += outcome.reshuffles;
+        stalemates += outcome.stalemate as usize;
+    }
+
+    let average = |total: usize| total as f64 / games.max(1) as f64;
+    seats
+        .iter_mut()
+        .for_each(|seat| seat.win_rate = average(seat.wins));
+
+    let report = Report {
+        seed,
+        games,
+        avg_turns: average(turns),
+        avg_cards_drawn: average(cards_drawn),
+        avg_reshuffles: average(reshuffles),
+        stalemates,
+        seats,
+    };
+
+    match matches.value_of("format") {
+        Some("json") => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+        _ => print!("{}", to_csv(&report)),
+    }
+}
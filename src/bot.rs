@@ -0,0 +1,169 @@
+use crate::game::{Action, Card, Color, Type};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::str::FromStr;
+use uuid::Uuid;
+
+// Everything a bot can see when deciding its next action: its own hand and the public state of the game
+#[derive(Debug, Clone)]
+pub struct BotView {
+    pub id: Uuid,
+    pub hand: Vec<Card>,
+    pub top: Card,
+    // Indexes of the cards in the hand that can be placed
+    pub allowed: Vec<usize>,
+    // Card counts of the other players, starting from the next one
+    pub opponents: Vec<(Uuid, usize)>,
+    // The bot has already placed a card this turn
+    pub placed: bool,
+    pub can_end: bool,
+    pub declared_uno: bool,
+    // A seven was placed and a player to swap hands with has to be chosen
+    pub swap_pending: bool,
+    // A wild card was placed and its color has not been chosen yet
+    pub needs_color: bool,
+}
+
+// A computer player. 'decide' is called repeatedly during the bot's turn and every action
+// goes through 'Game::handle' just like the ones sent by players.
+pub trait Bot: Debug + Send {
+    // Index into the hand of the card to place, chosen from 'view.allowed'
+    fn choose_card(&mut self, view: &BotView, rng: &mut StdRng) -> usize;
+
+    fn choose_color(&mut self, view: &BotView, rng: &mut StdRng) -> Color;
+
+    fn decide(&mut self, view: &BotView, rng: &mut StdRng) -> Action {
+        if view.swap_pending {
+            let target = view
+                .opponents
+                .iter()
+                .min_by_key(|(_, count)| *count)
+                .map(|(id, _)| *id)
+                .unwrap();
+            return Action::SwapHands(target);
+        }
+
+        if view.needs_color {
+            return Action::ColorSwitch(self.choose_color(view, rng));
+        }
+
+        if view.hand.len() == 1 && !view.declared_uno {
+            return Action::CallUno;
+        }
+
+        if !view.placed && !view.allowed.is_empty() {
+            return Action::PlaceCard(self.choose_card(view, rng));
+        }
+
+        if view.can_end {
+            Action::EndTurn
+        } else {
+            Action::DrawCard(1)
+        }
+    }
+}
+
+// Strategies the host can choose from when adding a bot
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, strum_macros::Display,
+)]
+pub enum BotStrategy {
+    // Places a random allowed card
+    Random,
+    // Gets rid of the most valuable card first
+    Greedy,
+    // Prefers the color it holds the most of and saves wild cards
+    Color,
+}
+
+impl BotStrategy {
+    pub fn create(self) -> Box<dyn Bot> {
+        match self {
+            BotStrategy::Random => Box::new(RandomBot),
+            BotStrategy::Greedy => Box::new(GreedyBot),
+            BotStrategy::Color => Box::new(ColorBot),
+        }
+    }
+}
+
+impl FromStr for BotStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "random" => Ok(BotStrategy::Random),
+            "greedy" => Ok(BotStrategy::Greedy),
+            "color" => Ok(BotStrategy::Color),
+            _ => Err(format!("Unknown bot strategy '{}'", s)),
+        }
+    }
+}
+
+// Returns the color the hand has the most cards of
+pub fn most_common_color(hand: &[Card]) -> Color {
+    Color::iter()
+        .into_iter()
+        .max_by_key(|color| hand.iter().filter(|card| card.color == *color).count())
+        .unwrap()
+}
+
+#[derive(Debug)]
+pub struct RandomBot;
+
+impl Bot for RandomBot {
+    fn choose_card(&mut self, view: &BotView, rng: &mut StdRng) -> usize {
+        *view.allowed.choose(rng).unwrap()
+    }
+
+    fn choose_color(&mut self, _: &BotView, rng: &mut StdRng) -> Color {
+        Color::iter().choose(rng).unwrap().clone()
+    }
+}
+
+#[derive(Debug)]
+pub struct GreedyBot;
+
+impl Bot for GreedyBot {
+    fn choose_card(&mut self, view: &BotView, _: &mut StdRng) -> usize {
+        *view
+            .allowed
+            .iter()
+            .max_by_key(|index| view.hand[**index].points())
+            .unwrap()
+    }
+
+    fn choose_color(&mut self, view: &BotView, _: &mut StdRng) -> Color {
+        most_common_color(&view.hand)
+    }
+}
+
+#[derive(Debug)]
+pub struct ColorBot;
+
+impl Bot for ColorBot {
+    fn choose_card(&mut self, view: &BotView, _: &mut StdRng) -> usize {
+        let wild_cards = [Type::Switch, Type::DrawFour];
+        let color = most_common_color(&view.hand);
+
+        // Wild cards are only used when nothing else can be placed
+        *view
+            .allowed
+            .iter()
+            .max_by_key(|index| {
+                let card = &view.hand[**index];
+                (
+                    !wild_cards.contains(&card.r#type),
+                    card.color == color,
+                    card.points(),
+                )
+            })
+            .unwrap()
+    }
+
+    fn choose_color(&mut self, view: &BotView, _: &mut StdRng) -> Color {
+        most_common_color(&view.hand)
+    }
+}
@@ -1,22 +1,176 @@
-use serde::{Deserialize, Serialize};
+use crate::game::MAX_PLAYERS;
+use crate::options::GameOptions;
+use crate::packets::{PacketType, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use std::fmt;
 
-#[derive(Serialize, Deserialize)]
-pub struct HTMLError {
-    pub r#type: String,
-    pub status_code: u64,
-    pub body: String,
+// Errors sent back to the client. The codes are part of the protocol and must stay stable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameError {
+    NotYourTurn,
+    IllegalCard(String),
+    IllegalDraw(String),
+    IllegalColorSwitch,
+    CannotEndTurn(String),
+    IllegalAction(String),
+    RoomFull,
+    GameInProgress,
+    MalformedPacket(String),
+    IncompatibleVersion(u32),
+    InvalidOptions(OptionsError),
+    AlreadyRegistered,
+    NotRegistered,
+    NotHost,
+    NotEnoughPlayers,
+    InvalidSession,
+    UnknownPlayer,
+    UnknownRoom,
+    WrongPassword,
+    InvalidInvite,
+    NotOwner,
+    Banned,
 }
 
-impl HTMLError {
-    pub fn to_json(err: HTMLError) -> String {
-        serde_json::to_string(&err).unwrap()
+impl GameError {
+    // Machine-readable code of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            GameError::NotYourTurn => "NOT_YOUR_TURN",
+            GameError::IllegalCard(_) => "ILLEGAL_CARD",
+            GameError::IllegalDraw(_) => "ILLEGAL_DRAW",
+            GameError::IllegalColorSwitch => "ILLEGAL_COLOR_SWITCH",
+            GameError::CannotEndTurn(_) => "CANNOT_END_TURN",
+            GameError::IllegalAction(_) => "ILLEGAL_ACTION",
+            GameError::RoomFull => "ROOM_FULL",
+            GameError::GameInProgress => "GAME_IN_PROGRESS",
+            GameError::MalformedPacket(_) => "MALFORMED_PACKET",
+            GameError::IncompatibleVersion(_) => "INCOMPATIBLE_VERSION",
+            GameError::InvalidOptions(_) => "INVALID_OPTIONS",
+            GameError::AlreadyRegistered => "ALREADY_REGISTERED",
+            GameError::NotRegistered => "NOT_REGISTERED",
+            GameError::NotHost => "NOT_HOST",
+            GameError::NotEnoughPlayers => "NOT_ENOUGH_PLAYERS",
+            GameError::InvalidSession => "INVALID_SESSION",
+            GameError::UnknownPlayer => "UNKNOWN_PLAYER",
+            GameError::UnknownRoom => "UNKNOWN_ROOM",
+            GameError::WrongPassword => "WRONG_PASSWORD",
+            GameError::InvalidInvite => "INVALID_INVITE",
+            GameError::NotOwner => "NOT_OWNER",
+            GameError::Banned => "BANNED",
+        }
+    }
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameError::NotYourTurn => write!(f, "It's not your turn"),
+            GameError::IllegalCard(message)
+            | GameError::IllegalDraw(message)
+            | GameError::CannotEndTurn(message)
+            | GameError::IllegalAction(message) => write!(f, "{}", message),
+            GameError::IllegalColorSwitch => write!(
+                f,
+                "The color can only be chosen once after placing a wild card"
+            ),
+            GameError::RoomFull => write!(f, "The room is full"),
+            GameError::GameInProgress => write!(f, "The game has already started"),
+            GameError::MalformedPacket(e) => write!(f, "Malformed packet: {}", e),
+            GameError::IncompatibleVersion(version) => write!(
+                f,
+                "Protocol version {} is not supported, the server supports versions {} to {}",
+                version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+            GameError::InvalidOptions(e) => write!(f, "{}", e),
+            GameError::AlreadyRegistered => write!(f, "Instance already exists"),
+            GameError::NotRegistered => write!(f, "Register before doing that"),
+            GameError::NotHost => write!(f, "Only the host can do that"),
+            GameError::NotEnoughPlayers => write!(f, "Cannot start the game alone"),
+            GameError::InvalidSession => write!(f, "Invalid or expired resume token"),
+            GameError::UnknownPlayer => write!(f, "There is no such player"),
+            GameError::UnknownRoom => write!(f, "There is no such room"),
+            GameError::WrongPassword => write!(f, "Wrong password"),
+            GameError::InvalidInvite => write!(f, "Invalid or already used invite"),
+            GameError::NotOwner => write!(f, "Only the creator of the room can do that"),
+            GameError::Banned => write!(f, "You are banned from the room"),
+        }
     }
+}
+
+impl From<GameError> for PacketType {
+    fn from(error: GameError) -> Self {
+        PacketType::Error {
+            code: error.code().to_string(),
+            message: error.to_string(),
+            request_id: None,
+        }
+    }
+}
+
+// Errors of the REST api are sent as '{"code": .., "message": ..}'
+impl ResponseError for GameError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            GameError::UnknownRoom => StatusCode::NOT_FOUND,
+            GameError::WrongPassword
+            | GameError::InvalidInvite
+            | GameError::NotOwner
+            | GameError::Banned => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "code": self.code(),
+            "message": self.to_string(),
+        }))
+    }
+}
+
+impl From<OptionsError> for GameError {
+    fn from(error: OptionsError) -> Self {
+        GameError::InvalidOptions(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OptionsError {
+    Malformed(String),
+    HandSize(usize),
+    NotEnoughCards(usize, usize), // player-count, hand-size
+    TargetScore,
+    TurnTimeout,
+    MaxPlayers(usize),
+}
 
-    pub fn new(status_code: u64, body: &str) -> HTMLError {
-        HTMLError {
-            r#type: String::from("ERROR"),
-            status_code,
-            body: String::from(body),
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionsError::Malformed(e) => write!(f, "Invalid game options: {}", e),
+            OptionsError::HandSize(size) => write!(
+                f,
+                "Hand size must be between 1 and {}, got {}",
+                GameOptions::MAX_HAND_SIZE,
+                size
+            ),
+            OptionsError::NotEnoughCards(players, size) => write!(
+                f,
+                "Not enough cards to deal {} cards to {} players",
+                size, players
+            ),
+            OptionsError::TargetScore => write!(f, "Target score must be greater than 0"),
+            OptionsError::TurnTimeout => write!(
+                f,
+                "Turn timeout must be between 1 and {} seconds and max timeouts greater than 0",
+                GameOptions::MAX_TURN_TIMEOUT
+            ),
+            OptionsError::MaxPlayers(max) => write!(
+                f,
+                "Max players must be between 2 and {}, got {}",
+                MAX_PLAYERS, max
+            ),
         }
     }
 }
@@ -1,24 +1,90 @@
-use crate::messages::WsMessage;
+use crate::bot::{most_common_color, Bot, BotStrategy, BotView};
+use crate::errors::GameError;
+use crate::options::{DrawRule, GameOptions};
 use crate::packets::*;
-use actix::prelude::Recipient;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{random, SeedableRng};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
+// Consecutive timed out turns after which the player is marked as afk
+const AFK_TIMEOUTS: usize = 2;
+// Players a single game can seat at most, bots included
+pub const MAX_PLAYERS: usize = 10;
+// Actions a bot can take during a single turn before the server plays the turn for it
+const MAX_BOT_ACTIONS: usize = 32;
+
 // https://www.unorules.org/wp-content/uploads/2021/03/All-Uno-cards-how-many-cards-in-uno.png
 
-type Socket = Recipient<WsMessage>;
+// Who should receive an outbound event
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Player(Uuid),
+    All,
+    AllExcept(Uuid),
+    Spectators,
+}
+
+// Packet produced by the game together with its recipients
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub target: Target,
+    pub packet: PacketType,
+}
+
+impl Event {
+    pub fn new(target: Target, packet: PacketType) -> Event {
+        Event { target, packet }
+    }
+}
 
+// Typed actions a player can perform in the game
+#[derive(Debug, Clone)]
+pub enum Action {
+    StartGame(GameOptions),
+    DrawCard(usize),
+    PlaceCard(usize),
+    EndTurn,
+    ColorSwitch(Color),
+    SwapHands(Uuid),
+    CallUno,
+    CatchUno(Uuid),
+    ChallengeDrawFour,
+    AddBot(BotStrategy),
+    RemoveBot(Uuid),
+    Kick(Uuid),
+    Ban(Uuid),
+    TransferHost(Uuid),
+}
+
+// Hand of the player at the moment they placed a draw-four, used to resolve challenges
+#[derive(Debug, Clone)]
+struct DrawFourRecord {
+    offender: Uuid,
+    hand: Vec<Card>,
+    prior_color: Color,
+}
+
+// Socket-free state of a single game. Actions are passed to 'Game::handle' and
+// the resulting events are returned to the caller to be delivered.
 #[derive(Debug)]
 pub struct Game {
     pub id: Uuid,
     pub active: bool,
     pub players: Players,
     pub spectators: HashMap<Uuid, Player>,
+    // Seats of the room, at most 'MAX_PLAYERS'
+    pub max_players: usize,
+    // Strategies of the players controlled by the server
+    bots: HashMap<Uuid, Box<dyn Bot>>,
     pub current_turn: Option<Uuid>,
+    // Incremented every time the turn changes, used to tell turn timers apart
+    pub turn: usize,
+    pub turn_deadline: Option<SystemTime>,
 
     pub deck: VecDeque<Card>,
     pub placed_deck: VecDeque<Card>,
@@ -26,29 +92,50 @@ pub struct Game {
     pub draw_stack: usize,
     pub block_stack: usize,
     pub reversed: bool,
+    // A seven was placed and the player has to choose who to swap hands with
+    swap_pending: bool,
+    draw_four: Option<DrawFourRecord>,
 
+    pub options: GameOptions,
     pub statistics: GameStatistics,
+
+    // Cumulative scores of the match, kept across rounds
+    pub scores: HashMap<Uuid, usize>,
+    pub round: usize,
+    first_turn: Option<Uuid>,
+
+    // Seed driving every shuffle of the game, allows the game to be reproduced
+    pub seed: u64,
+    rng: StdRng,
+
+    events: Vec<Event>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct GameStatistics {
+    pub seed: u64,
     pub start_time: Option<SystemTime>,
     pub end_time: Option<SystemTime>,
     pub player_count: usize,
     pub spectator_count: usize,
     pub cards_placed: usize,
     pub cards_drawn: usize,
+    pub reshuffles: usize,
+    pub deadlocked: bool,
 }
 
 impl GameStatistics {
     pub fn new() -> GameStatistics {
         GameStatistics {
+            seed: 0,
             start_time: None,
             end_time: None,
             player_count: 0,
             spectator_count: 0,
             cards_placed: 0,
             cards_drawn: 0,
+            reshuffles: 0,
+            deadlocked: false,
         }
     }
 
@@ -67,6 +154,10 @@ impl GameStatistics {
     pub fn card_drawn(&mut self) {
         self.cards_drawn += 1;
     }
+
+    pub fn deck_recycled(&mut self) {
+        self.reshuffles += 1;
+    }
 }
 
 impl Default for GameStatistics {
@@ -100,10 +191,13 @@ impl Players {
         self.len() == 0
     }
     // Returns a list of tuples containing the uuid of the player and it's username
-    pub fn map_username(&self) -> Vec<(Uuid, String)> {
+    pub fn map_username(&self) -> Vec<PlayerEntry> {
         self.0
             .iter()
-            .map(|(k, p)| (*k, p.username.clone()))
+            .map(|(k, p)| PlayerEntry {
+                id: *k,
+                username: p.username.clone(),
+            })
             .collect()
     }
     // Returns a list of players ordered by the number of cards they have (asc)
@@ -156,6 +250,13 @@ impl Players {
             current.0
         }
     }
+    // Returns the players in the order they will play, starting from the next player
+    pub fn turn_order(&self, reversed: bool) -> Vec<Uuid> {
+        let mut players = Players(self.0.clone());
+        (0..self.len())
+            .map(|_| players.next_player(reversed))
+            .collect()
+    }
     // Predicts which player would be next if 'next_player' would be called.
     pub fn predict_next(&self, reversed: bool) -> Uuid {
         let mut players = self.0.clone();
@@ -173,90 +274,877 @@ impl Players {
 
 impl Game {
     pub fn new() -> Game {
+        Game::with_seed(random())
+    }
+
+    pub fn with_seed(seed: u64) -> Game {
+        let mut rng = StdRng::seed_from_u64(seed);
+
         Game {
             id: Uuid::new_v4(),
             active: false,
             players: Players::default(),
             spectators: HashMap::new(),
-            deck: Card::generate_deck(),
+            max_players: MAX_PLAYERS,
+            bots: HashMap::new(),
+            deck: Card::generate_deck(&mut rng),
             current_turn: None,
+            turn: 0,
+            turn_deadline: None,
             placed_deck: VecDeque::new(),
             draw_stack: 0,
             block_stack: 0,
             reversed: false,
-            statistics: GameStatistics::default(),
+            swap_pending: false,
+            draw_four: None,
+            options: GameOptions::default(),
+            statistics: GameStatistics {
+                seed,
+                ..GameStatistics::default()
+            },
+            scores: HashMap::new(),
+            round: 0,
+            first_turn: None,
+            seed,
+            rng,
+            events: Vec::new(),
+        }
+    }
+
+    // Returns the events queued since the last call
+    fn flush(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn join(&mut self, id: Uuid) -> Vec<Event> {
+        // Connections to a full room can still watch and take a seat once one frees up
+        if self.players.len() >= self.max_players {
+            self.spectators.insert(id, Player::new(id));
+            self.error(&id, GameError::RoomFull);
+            return self.flush();
+        }
+
+        self.players.insert(id, Player::new(id));
+
+        self.emit(
+            &id,
+            PacketType::Message {
+                sender: "Server".to_string(),
+                content: format!("{} is your own id", &id),
+            },
+        );
+
+        self.flush()
+    }
+
+    // Adds a connection to a running game as a spectator. Spectators only receive public events.
+    pub fn spectate(&mut self, id: Uuid) -> Vec<Event> {
+        self.spectators.insert(id, Player::new(id));
+
+        self.emit(
+            &id,
+            PacketType::Message {
+                sender: "Server".to_string(),
+                content: "The game has already started, you are spectating".to_string(),
+            },
+        );
+
+        self.flush()
+    }
+
+    pub fn register(&mut self, id: Uuid, username: &str) -> Vec<Event> {
+        if self.spectators.contains_key(&id) {
+            self.register_spectator(id, username);
+            return self.flush();
+        }
+
+        if self.get_player(&id).is_connected {
+            self.error(&id, GameError::AlreadyRegistered);
+            return self.flush();
+        }
+        // Initialize the player
+        self.init_player(&id, username);
+
+        // Broadcast the join-event
+        self.broadcast_ignore_self(
+            id,
+            PacketType::Connect {
+                id,
+                username: username.to_string(),
+            },
+        );
+
+        // Emit the current game-data to the player
+        self.emit(
+            &id,
+            PacketType::GameData {
+                id,
+                username: username.to_string(),
+                players: self.players.map_username(),
+            },
+        );
+
+        self.flush()
+    }
+
+    pub fn leave(&mut self, id: Uuid) -> Vec<Event> {
+        if self.active && self.players.contains_key(&id) {
+            self.remove_player(id, "left the game");
+            self.play_bots();
+            return self.flush();
+        }
+
+        if let Some(player) = self.players.get(&id) {
+            let username = player.username.clone();
+            self.players.remove(&id);
+            self.broadcast(PacketType::Disconnect { id, username });
+            self.assign_host();
+        } else {
+            self.spectators.remove(&id);
+        }
+
+        self.flush()
+    }
+
+    // Marks the player as disconnected while keeping their seat and hand so the session can be resumed
+    pub fn disconnect(&mut self, id: Uuid) -> Vec<Event> {
+        if let Some(player) = self.players.get_mut(&id) {
+            player.is_connected = false;
+            player.disconnects += 1;
+            let username = player.username.clone();
+
+            self.broadcast_ignore_self(
+                id,
+                PacketType::ConnectionUpdate {
+                    id,
+                    connected: false,
+                },
+            );
+            self.broadcast(PacketType::Message {
+                sender: "Server".to_string(),
+                content: format!("{} lost connection", username),
+            });
+            self.assign_host();
+        }
+
+        self.flush()
+    }
+
+    // Gives the seat back to a disconnected player and sends them the current state of the game
+    pub fn reconnect(&mut self, id: Uuid) -> Vec<Event> {
+        if let Some(player) = self.players.get_mut(&id) {
+            player.is_connected = true;
+
+            self.broadcast_ignore_self(
+                id,
+                PacketType::ConnectionUpdate {
+                    id,
+                    connected: true,
+                },
+            );
+            self.send_state(id);
+            self.assign_host();
+        }
+
+        self.flush()
+    }
+
+    // Sends the full state of the game to a player or spectator whose client lost track of it
+    pub fn snapshot(&mut self, id: Uuid) -> Vec<Event> {
+        self.send_state(id);
+        self.flush()
+    }
+
+    fn register_spectator(&mut self, id: Uuid, username: &str) {
+        let spectator = self.spectators.get_mut(&id).unwrap();
+
+        if spectator.is_connected {
+            self.error(&id, GameError::AlreadyRegistered);
+            return;
+        }
+        spectator.username = String::from(username);
+        spectator.is_connected = true;
+
+        self.send_state(id);
+    }
+
+    // Lets a registered spectator join the game. During a running game they are seated when the next round begins.
+    pub fn take_seat(&mut self, id: Uuid) -> Vec<Event> {
+        let Some(spectator) = self.spectators.get_mut(&id) else {
+            return self.flush();
+        };
+
+        if !spectator.is_connected {
+            self.error(&id, GameError::NotRegistered);
+            return self.flush();
+        }
+
+        if self.players.len() >= self.max_players {
+            self.error(&id, GameError::RoomFull);
+            return self.flush();
+        }
+
+        spectator.waiting = true;
+
+        if self.active {
+            self.emit(
+                &id,
+                PacketType::Message {
+                    sender: "Server".to_string(),
+                    content: "You will take a seat when the next round begins".to_string(),
+                },
+            );
+        } else {
+            self.seat_spectators();
+
+            // The options leave no cards for another hand, the spectator stays waiting in case
+            // the host starts the game with other options
+            if self.spectators.contains_key(&id) {
+                if let Err(e) = self
+                    .options
+                    .validate(self.players.len() + 1, Card::deck_size())
+                {
+                    self.error(&id, e.into());
+                }
+            }
+        }
+
+        self.flush()
+    }
+
+    // Moves the waiting spectators to the game for as long as there are enough cards to deal
+    fn seat_spectators(&mut self) {
+        let waiting: Vec<Uuid> = self
+            .spectators
+            .values()
+            .filter(|s| s.waiting)
+            .map(|s| s.id)
+            .collect();
+
+        for id in waiting {
+            if self.players.len() >= self.max_players
+                || self
+                    .options
+                    .validate(self.players.len() + 1, Card::deck_size())
+                    .is_err()
+            {
+                return;
+            }
+
+            let mut player = self.spectators.remove(&id).unwrap();
+            player.waiting = false;
+            let username = player.username.clone();
+            self.players.insert(id, player);
+
+            self.broadcast_ignore_self(
+                id,
+                PacketType::Connect {
+                    id,
+                    username: username.clone(),
+                },
+            );
+            self.emit(
+                &id,
+                PacketType::GameData {
+                    id,
+                    username,
+                    players: self.players.map_username(),
+                },
+            );
+        }
+    }
+
+    // Emits everything a player or spectator needs to rebuild their view of the game
+    fn send_state(&mut self, id: Uuid) {
+        let Some(username) = self
+            .players
+            .get(&id)
+            .or(self.spectators.get(&id))
+            .map(|p| p.username.clone())
+        else {
+            return;
+        };
+
+        self.emit(
+            &id,
+            PacketType::GameData {
+                id,
+                username,
+                players: self.players.map_username(),
+            },
+        );
+
+        if self.active {
+            let snapshot = self.snapshot_of(&id);
+            self.emit(&id, snapshot);
+        }
+    }
+
+    // Returns the state of the running game as seen by the given player or spectator
+    fn snapshot_of(&self, id: &Uuid) -> PacketType {
+        let top = self.placed_deck.front().unwrap().clone();
+        let current = self.current_turn.unwrap();
+        let turn_order = self.players.turn_order(self.reversed);
+
+        let opponents = turn_order
+            .iter()
+            .filter(|player| *player != id)
+            .map(|player| {
+                let player = self.get_player(player);
+                OpponentEntry {
+                    id: player.id,
+                    username: player.username.clone(),
+                    cards: player.cards.len(),
+                }
+            })
+            .collect();
+
+        let hand = self
+            .players
+            .get(id)
+            .map(|p| p.cards.clone())
+            .unwrap_or_default();
+        let allowed = if current == *id {
+            self.allowed_cards(id)
+        } else {
+            Vec::new()
+        };
+
+        PacketType::Snapshot {
+            turn_order,
+            current,
+            next: self.players.predict_next(self.reversed),
+            reversed: self.reversed,
+            draw_stack: self.draw_stack,
+            block_stack: self.block_stack,
+            color: top.color.clone(),
+            top,
+            deadline: self.turn_deadline,
+            opponents,
+            hand,
+            allowed,
+            deck_size: self.deck.len(),
+        }
+    }
+
+    pub fn get_player(&self, id: &Uuid) -> &Player {
+        self.players.get(id).unwrap()
+    }
+
+    pub fn emit(&mut self, id: &Uuid, packet: PacketType) {
+        // Bots read the game directly and have nowhere to receive packets
+        if self.bots.contains_key(id) {
+            return;
+        }
+        self.events.push(Event::new(Target::Player(*id), packet));
+    }
+
+    pub fn error(&mut self, id: &Uuid, error: GameError) {
+        self.emit(id, error.into());
+    }
+
+    pub fn broadcast(&mut self, packet: PacketType) {
+        self.events.push(Event::new(Target::All, packet));
+    }
+
+    pub fn broadcast_ignore_self(&mut self, self_id: Uuid, packet: PacketType) {
+        self.events
+            .push(Event::new(Target::AllExcept(self_id), packet));
+    }
+
+    fn init_player(&mut self, id: &Uuid, username: &str) {
+        let host = !self.players.players().iter().any(|p| p.is_host);
+        let p: Option<&mut Player> = self.players.get_mut(id);
+
+        if let Some(p) = p {
+            p.username = String::from(username);
+            p.is_connected = true;
+            p.is_host = host;
+        }
+
+        if host {
+            self.emit(
+                id,
+                PacketType::Message {
+                    sender: "Server".to_string(),
+                    content: "You are the host".to_string(),
+                },
+            )
+        }
+    }
+
+    // Handles an action sent by the player and returns the resulting events
+    pub fn handle(&mut self, id: Uuid, action: Action) -> Vec<Event> {
+        if self.players.contains_key(&id) {
+            self.apply(id, action);
+            self.play_bots();
+        }
+
+        self.flush()
+    }
+
+    fn apply(&mut self, id: Uuid, action: Action) {
+        match action {
+            Action::StartGame(options) => {
+                self.request_start(id, options);
+                return;
+            }
+            Action::AddBot(strategy) => {
+                self.add_bot(id, strategy);
+                return;
+            }
+            Action::RemoveBot(bot) => {
+                self.remove_bot(id, bot);
+                return;
+            }
+            Action::Kick(target) => {
+                self.kick(id, target, false);
+                return;
+            }
+            Action::Ban(target) => {
+                self.kick(id, target, true);
+                return;
+            }
+            Action::TransferHost(target) => {
+                self.transfer_host(id, target);
+                return;
+            }
+            _ => {}
+        }
+
+        // Uno can be called and caught outside of the player's own turn
+        match action {
+            Action::CallUno => {
+                self.call_uno(id);
+                return;
+            }
+            Action::CatchUno(target) => {
+                self.catch_uno(id, target);
+                return;
+            }
+            _ => {}
+        }
+
+        // Jumping in takes the turn from the current player
+        if let Action::PlaceCard(index) = action {
+            if self.can_jump_in(&id, index) {
+                self.jump_in(id);
+            }
+        }
+
+        if !self.active || self.current_turn != Some(id) {
+            self.error(&id, GameError::NotYourTurn);
+            return;
+        }
+
+        // The player is active again
+        let p = self.players.get_mut(&id).unwrap();
+        p.timeouts = 0;
+        if p.afk {
+            p.afk = false;
+            self.broadcast(PacketType::PlayerAfk { id, afk: false });
+        }
+
+        if let Err(e) = self.validate(&id, &action) {
+            self.error(&id, e);
+            return;
+        }
+
+        match action {
+            Action::StartGame(_)
+            | Action::AddBot(_)
+            | Action::RemoveBot(_)
+            | Action::Kick(_)
+            | Action::Ban(_)
+            | Action::TransferHost(_)
+            | Action::CallUno
+            | Action::CatchUno(_) => {}
+            Action::DrawCard(amount) => {
+                self.draw_cards(amount, id);
+                self.update_card_status(&id);
+                self.update_allowed_status(&id);
+            }
+            Action::PlaceCard(index) => {
+                self.place_card(index, id);
+                self.update_card_status(&id);
+                self.update_allowed_status(&id);
+            }
+            Action::EndTurn => self.end_turn(id),
+            Action::ColorSwitch(color) => {
+                self.switch_color(color);
+                self.update_card_status(&id);
+                self.update_allowed_status(&id);
+            }
+            Action::SwapHands(target) => {
+                self.swap_hands(id, target);
+                self.update_allowed_status(&id);
+            }
+            Action::ChallengeDrawFour => self.challenge_draw_four(id),
+        }
+    }
+
+    // Checks an action of the current player against the rules before it is applied
+    fn validate(&self, id: &Uuid, action: &Action) -> Result<(), GameError> {
+        let wild_cards = [Type::Switch, Type::DrawFour];
+        let player = self.get_player(id);
+        let top = self.placed_deck.front().unwrap();
+        let placed = player.actions.contains(&Actions::PlaceCard);
+        let drawn = player
+            .actions
+            .iter()
+            .filter(|a| **a == Actions::DrawCard)
+            .count();
+
+        match action {
+            Action::PlaceCard(index) => {
+                let card = player.cards.get(*index).ok_or_else(|| {
+                    GameError::IllegalCard(format!("There is no card at index {}", index))
+                })?;
+
+                if !self.allowed_cards(id).contains(card) {
+                    return Err(GameError::IllegalCard(format!(
+                        "{} {} cannot be placed on {} {}",
+                        card.color, card.r#type, top.color, top.r#type
+                    )));
+                }
+            }
+            Action::DrawCard(amount) => {
+                if *amount == 0 {
+                    return Err(GameError::IllegalDraw(
+                        "At least one card has to be drawn".to_string(),
+                    ));
+                }
+
+                if placed {
+                    return Err(GameError::IllegalDraw(
+                        "Cannot draw after placing a card".to_string(),
+                    ));
+                }
+
+                if self.options.forced_play && !self.allowed_cards(id).is_empty() {
+                    return Err(GameError::IllegalDraw(
+                        "You have to place a card when you are able to".to_string(),
+                    ));
+                }
+
+                match self.options.draw_rule {
+                    DrawRule::DrawThree if drawn + amount > 3 => {
+                        return Err(GameError::IllegalDraw(format!(
+                            "You can draw {} more cards this turn",
+                            3usize.saturating_sub(drawn)
+                        )));
+                    }
+                    DrawRule::UntilPlayable if *amount > 1 => {
+                        return Err(GameError::IllegalDraw(
+                            "Cards have to be drawn one at a time".to_string(),
+                        ));
+                    }
+                    DrawRule::UntilPlayable
+                        if drawn > 0
+                            && player
+                                .cards
+                                .last()
+                                .is_some_and(|card| self.allowed_cards(id).contains(card)) =>
+                    {
+                        return Err(GameError::IllegalDraw(
+                            "You already drew a playable card".to_string(),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            Action::ColorSwitch(_)
+                if !wild_cards.contains(&top.r#type)
+                    || top.owner != Some(*id)
+                    || top.base_color.is_some() =>
+            {
+                return Err(GameError::IllegalColorSwitch);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // Adds a bot to the game before it has started
+    fn add_bot(&mut self, id: Uuid, strategy: BotStrategy) {
+        if let Err(e) = self.check_lobby_action(&id) {
+            self.error(&id, e);
+            return;
+        }
+
+        if self.players.len() >= self.max_players {
+            self.error(&id, GameError::RoomFull);
+            return;
+        }
+
+        // The deck is shuffled again when the game starts
+        if let Err(e) = self
+            .options
+            .validate(self.players.len() + 1, Card::deck_size())
+        {
+            self.error(&id, e.into());
+            return;
+        }
+
+        let bot = Uuid::new_v4();
+        let username = format!("{} bot {}", strategy, self.bots.len() + 1);
+
+        let mut player = Player::new(bot);
+        player.username = username.clone();
+        player.is_connected = true;
+
+        self.players.insert(bot, player);
+        self.bots.insert(bot, strategy.create());
+        self.broadcast(PacketType::Connect { id: bot, username });
+    }
+
+    fn remove_bot(&mut self, id: Uuid, bot: Uuid) {
+        if let Err(e) = self.check_lobby_action(&id) {
+            self.error(&id, e);
+            return;
+        }
+
+        if !self.bots.contains_key(&bot) {
+            self.error(&id, GameError::UnknownPlayer);
+            return;
+        }
+
+        let username = self.get_player(&bot).username.clone();
+        self.bots.remove(&bot);
+        self.players.remove(&bot);
+        self.broadcast(PacketType::Disconnect { id: bot, username });
+    }
+
+    // Removes a player or a spectator from the room. The room closes the connection of the target
+    // once it has received the 'Kicked' packet.
+    fn kick(&mut self, id: Uuid, target: Uuid, banned: bool) {
+        if !self.is_host(&id) {
+            self.error(&id, GameError::NotHost);
+            return;
+        }
+
+        if target == id {
+            self.error(
+                &id,
+                GameError::IllegalAction("The host can't kick themselves".to_string()),
+            );
+            return;
+        }
+
+        if !self.players.contains_key(&target) && !self.spectators.contains_key(&target) {
+            self.error(&id, GameError::UnknownPlayer);
+            return;
+        }
+
+        self.broadcast(PacketType::Kicked { id: target, banned });
+
+        if self.spectators.remove(&target).is_some() {
+            return;
+        }
+
+        let reason = if banned { "was banned" } else { "was kicked" };
+        if self.active {
+            self.remove_player(target, reason);
+            return;
+        }
+
+        let username = self.get_player(&target).username.clone();
+        self.bots.remove(&target);
+        self.players.remove(&target);
+        self.broadcast(PacketType::Disconnect {
+            id: target,
+            username: username.clone(),
+        });
+        self.broadcast(PacketType::Message {
+            sender: "Server".to_string(),
+            content: format!("{} {}", username, reason),
+        });
+    }
+
+    fn transfer_host(&mut self, id: Uuid, target: Uuid) {
+        if !self.is_host(&id) {
+            self.error(&id, GameError::NotHost);
+            return;
+        }
+
+        let connected = match self.players.get(&target) {
+            Some(player) => player.is_connected && target != id && !self.is_bot(&target),
+            None => {
+                self.error(&id, GameError::UnknownPlayer);
+                return;
+            }
+        };
+
+        if !connected {
+            self.error(
+                &id,
+                GameError::IllegalAction(
+                    "The host can only be passed to another connected player".to_string(),
+                ),
+            );
+            return;
+        }
+
+        self.set_host(Some(target));
+    }
+
+    // Passes the host to the first connected player if the host left or lost connection
+    fn assign_host(&mut self) {
+        if self
+            .players
+            .players()
+            .iter()
+            .any(|p| p.is_host && p.is_connected)
+        {
+            return;
+        }
+
+        let next = self
+            .players
+            .players()
+            .iter()
+            .find(|p| p.is_connected && !self.is_bot(&p.id))
+            .map(|p| p.id);
+        self.set_host(next);
+    }
+
+    fn set_host(&mut self, host: Option<Uuid>) {
+        for id in self.players.keys_mut() {
+            self.players.get_mut(&id).unwrap().is_host = Some(id) == host;
+        }
+
+        if let Some(id) = host {
+            self.broadcast(PacketType::HostChanged { id });
+            self.emit(
+                &id,
+                PacketType::Message {
+                    sender: "Server".to_string(),
+                    content: "You are the host".to_string(),
+                },
+            );
         }
     }
 
-    pub fn leave(&mut self, id: Uuid) {
-        if self.players.contains_key(&id) {
-            self.players.remove(&id);
-        } else if self.spectators.contains_key(&id) {
-            self.spectators.remove(&id);
-        } else {
-            return;
-        }
+    pub fn is_bot(&self, id: &Uuid) -> bool {
+        self.bots.contains_key(id)
+    }
 
-        if self.active {
-            self.broadcast(&to_json(PacketType::Message(
-                "Server".to_string(),
-                "Game ended due to one of the players leaving".to_string(),
-            )));
-            self.end();
+    // Returns what the player is able to see of the game
+    pub fn bot_view(&self, id: &Uuid) -> BotView {
+        let player = self.get_player(id);
+        let top = self.placed_deck.front().unwrap().clone();
+        let allowed = self.allowed_cards(id);
+
+        let opponents = self
+            .players
+            .turn_order(self.reversed)
+            .into_iter()
+            .filter(|other| other != id)
+            .map(|other| (other, self.get_player(&other).cards.len()))
+            .collect();
+
+        BotView {
+            id: *id,
+            allowed: (0..player.cards.len())
+                .filter(|i| allowed.contains(&player.cards[*i]))
+                .collect(),
+            opponents,
+            placed: player.actions.contains(&Actions::PlaceCard),
+            can_end: self.can_end_turn(id),
+            declared_uno: player.declared_uno,
+            swap_pending: self.swap_pending,
+            needs_color: self.needs_color(id),
+            hand: player.cards.clone(),
+            top,
         }
     }
 
-    pub fn get_player(&mut self, id: &Uuid) -> &Player {
-        self.players.get(id).unwrap()
+    // Returns true if the player placed a wild card and has not chosen its color yet
+    fn needs_color(&self, id: &Uuid) -> bool {
+        let wild_cards = [Type::Switch, Type::DrawFour];
+        let top = self.placed_deck.front().unwrap();
+
+        wild_cards.contains(&top.r#type) && top.owner == Some(*id) && top.base_color.is_none()
     }
 
-    fn send_message(&self, message: &str, id: &Uuid) {
-        if let Some(socket_recipient) = self.players.get(id) {
-            let _ = socket_recipient
-                .socket
-                .do_send(WsMessage(message.to_owned()));
-        } else {
-            println!("Couldn't find anyone to send message to");
+    // Lets the bots play for as long as it's the turn of one
+    fn play_bots(&mut self) {
+        while let Some(id) = self
+            .current_turn
+            .filter(|id| self.active && self.is_bot(id))
+        {
+            let turn = self.turn;
+
+            for _ in 0..MAX_BOT_ACTIONS {
+                let view = self.bot_view(&id);
+                let bot = self.bots.get_mut(&id).unwrap();
+                let action = bot.decide(&view, &mut self.rng);
+
+                self.apply(id, action);
+
+                if !self.active || self.turn != turn {
+                    break;
+                }
+            }
+
+            // The bot got stuck, end the turn for it
+            if self.active && self.turn == turn {
+                self.auto_play(id);
+            }
         }
     }
 
-    pub fn emit(&self, id: &Uuid, data: &str) {
-        self.send_message(data, id);
+    // Returns true if the player is the host of the room
+    pub fn is_host(&self, id: &Uuid) -> bool {
+        self.players.get(id).is_some_and(|p| p.is_host)
     }
 
-    pub fn broadcast(&self, data: &str) {
-        for id in self.players.keys() {
-            self.send_message(data, id);
+    // Host-only actions that can only be done before the game has started
+    fn check_lobby_action(&self, id: &Uuid) -> Result<(), GameError> {
+        if !self.is_host(id) {
+            return Err(GameError::NotHost);
         }
-    }
 
-    pub fn broadcast_ignore_self(&self, self_id: Uuid, data: &str) {
-        for id in self.players.keys() {
-            if &self_id != id {
-                self.send_message(data, id);
-            }
+        if self.active {
+            return Err(GameError::GameInProgress);
         }
+
+        Ok(())
     }
 
-    pub fn init_player(&mut self, id: &Uuid, username: &str) {
-        let host = self.players.len() == 1;
-        let p: Option<&mut Player> = self.players.get_mut(id);
+    fn request_start(&mut self, id: Uuid, options: GameOptions) {
+        if let Err(e) = self.check_lobby_action(&id) {
+            self.error(&id, e);
+            return;
+        }
 
-        if let Some(p) = p {
-            p.username = String::from(username);
-            p.is_connected = true;
-            p.is_host = host;
+        if self.players.len() < 2 {
+            self.error(&id, GameError::NotEnoughPlayers);
+            return;
         }
 
-        if host {
-            self.emit(
-                id,
-                &to_json(PacketType::Message(
-                    "Server".to_string(),
-                    "You are the host".to_string(),
-                )),
-            )
+        // A finished game or match leaves its cards and scores behind
+        if !self.placed_deck.is_empty() {
+            self.reset_cards();
+            self.scores.clear();
+            self.round = 0;
+            self.first_turn = None;
+        }
+
+        if let Err(e) = options.validate(self.players.len(), self.deck.len()) {
+            self.error(&id, e.into());
+            return;
         }
+
+        self.options = options;
+        self.seat_spectators();
+        self.start();
+
+        self.broadcast(PacketType::Message {
+            sender: "Server".to_string(),
+            content: "The host has started the game".to_string(),
+        });
     }
 
     pub fn start(&mut self) {
@@ -267,80 +1155,301 @@ impl Game {
         self.active = true;
 
         for id in self.players.keys_mut() {
-            self.draw_cards(8, id);
+            self.deal_cards(self.options.hand_size, id);
             self.update_card_status(&id);
         }
 
+        self.round += 1;
         self.give_turn();
+        self.first_turn = self.current_turn;
         self.statistics.game_started();
-
-        self.broadcast(&to_json(PacketType::Message(
-            "Server".to_string(),
-            "The host has started the game".to_string(),
-        )));
     }
 
-    pub fn end(&mut self) {
-        println!("Player won the game");
+    // Returns the uuid of the winner
+    pub fn end(&mut self) -> Uuid {
         self.statistics.game_ended();
         self.statistics.player_count = self.players.len();
+        self.statistics.spectator_count = self.spectators.len();
         let mut placements = self.players.sort_by_cards();
         let winner = placements.pop_front().unwrap();
+        let winner_id = winner.id;
 
-        let p = PacketType::WinUpdate(
-            winner.id,
-            winner.username.clone(),
-            placements.iter().map(|p| p.username.clone()).collect(),
-            self.statistics.clone(),
-        );
+        let p = PacketType::WinUpdate {
+            id: winner.id,
+            username: winner.username.clone(),
+            placements: placements.iter().map(|p| p.username.clone()).collect(),
+            statistics: self.statistics.clone(),
+        };
 
-        self.broadcast(&to_json(p));
+        self.broadcast(p);
 
         self.active = false;
+        winner_id
+    }
+
+    // Ends the current round. In match mode the round is scored and the next one is started until
+    // a player reaches the target score.
+    fn end_round(&mut self) {
+        let winner = self.end();
+
+        if let Some(target) = self.options.target_score {
+            if self.score_round(winner, target).is_none() && self.players.len() > 1 {
+                self.new_round();
+            }
+        }
+    }
+
+    // Credits the points left in the hands of the other players to the winner of the round.
+    // Returns the winner of the match if the target score has been reached.
+    fn score_round(&mut self, winner: Uuid, target: usize) -> Option<Uuid> {
+        let points: usize = self
+            .players
+            .players()
+            .iter()
+            .flat_map(|p| p.cards.iter())
+            .map(Card::points)
+            .sum();
+
+        *self.scores.entry(winner).or_insert(0) += points;
+
+        let champion = self
+            .scores
+            .iter()
+            .find(|(_, score)| **score >= target)
+            .map(|(id, _)| *id);
+
+        self.broadcast(PacketType::MatchUpdate {
+            round: self.round,
+            scores: self.scoreboard(),
+            winner: champion,
+        });
+
+        champion
+    }
+
+    // Returns the scores of the match in seating order
+    pub fn scoreboard(&self) -> Vec<ScoreEntry> {
+        self.players
+            .players()
+            .iter()
+            .map(|p| ScoreEntry {
+                id: p.id,
+                username: p.username.clone(),
+                score: self.scores.get(&p.id).copied().unwrap_or(0),
+            })
+            .collect()
+    }
+
+    // Resets the cards and starts the next round of the match, the first turn moving to the next player
+    fn new_round(&mut self) {
+        self.reset_cards();
+        self.seat_spectators();
+
+        // Rotate the seats so that the player after the previous starter plays first
+        if let Some(previous) = self.first_turn.filter(|id| self.players.contains_key(id)) {
+            while self.players.predict_next(false) != previous {
+                self.players.next_player(false);
+            }
+            self.players.next_player(false);
+        }
+
+        self.start();
+
+        self.broadcast(PacketType::Message {
+            sender: "Server".to_string(),
+            content: format!("Round {} has started", self.round),
+        });
+    }
+
+    // Takes the cards back from the players and shuffles a new deck
+    fn reset_cards(&mut self) {
+        for id in self.players.keys_mut() {
+            let p = self.players.get_mut(&id).unwrap();
+            p.cards.clear();
+            p.actions.clear();
+            p.declared_uno = false;
+        }
+
+        self.deck = Card::generate_deck(&mut self.rng);
+        self.placed_deck.clear();
+        self.current_turn = None;
+        self.draw_stack = 0;
+        self.block_stack = 0;
+        self.reversed = false;
+        self.swap_pending = false;
+        self.draw_four = None;
     }
 
     pub fn give_turn(&mut self) {
         let current = self.next_turn();
+        let deadline = self.start_turn_timer();
 
         self.emit(
             &current,
-            &to_json(PacketType::Message(
-                "Server".to_string(),
-                "Your turn".to_string(),
-            )),
+            PacketType::Message {
+                sender: "Server".to_string(),
+                content: "Your turn".to_string(),
+            },
         );
 
-        self.broadcast(&to_json(PacketType::TurnUpdate(
+        self.broadcast(PacketType::TurnUpdate {
             current,
-            self.players.predict_next(self.reversed),
-        )));
+            next: self.players.predict_next(self.reversed),
+            deadline,
+        });
 
         self.update_allowed_status(&current);
     }
 
-    pub fn end_turn(&mut self, id: Uuid) {
+    // Starts a new turn and returns the time it has to be ended by
+    fn start_turn_timer(&mut self) -> Option<SystemTime> {
+        self.turn += 1;
+        self.turn_deadline = self
+            .options
+            .turn_timeout
+            .and_then(|secs| SystemTime::now().checked_add(Duration::from_secs(secs)));
+        self.turn_deadline
+    }
+
+    // Called when the deadline of the given turn has passed. The server draws the required cards
+    // and ends the turn for the player, removing them after too many consecutive timeouts.
+    pub fn timeout(&mut self, turn: usize) -> Vec<Event> {
+        if !self.active || turn != self.turn {
+            return self.flush();
+        }
+
+        let id = self.current_turn.unwrap();
+        let p = self.players.get_mut(&id).unwrap();
+        p.timeouts += 1;
+
+        if p.timeouts >= self.options.max_timeouts {
+            self.remove_player(id, "was removed for being inactive");
+            self.play_bots();
+            return self.flush();
+        }
+
+        if p.timeouts >= AFK_TIMEOUTS && !p.afk {
+            p.afk = true;
+            self.broadcast(PacketType::PlayerAfk { id, afk: true });
+        }
+
+        self.auto_play(id);
+        self.play_bots();
+        self.flush()
+    }
+
+    // Draws the required cards and ends the turn on behalf of the player
+    fn auto_play(&mut self, id: Uuid) {
+        if self.swap_pending {
+            let target = self.players.predict_next(self.reversed);
+            self.swap_hands(id, target);
+        }
+
+        if self.needs_color(&id) {
+            let color = most_common_color(&self.get_player(&id).cards);
+            self.switch_color(color);
+        }
+
+        while !self.can_end_turn(&id) {
+            self.draw_cards(1, id);
+        }
+
+        self.end_turn(id);
+    }
+
+    // Removes a player from a running game. The game ends if less than two players are left.
+    fn remove_player(&mut self, id: Uuid, reason: &str) {
+        let mut cards = self.get_player(&id).cards.clone();
+        let username = self.get_player(&id).username.clone();
+
+        // Return the cards to the bottom of the deck to keep the amount of cards in the game
+        cards.iter_mut().for_each(|card| card.reset());
+        self.deck.extend(cards);
+
+        self.players.remove(&id);
+        self.bots.remove(&id);
+        if self.draw_four.as_ref().is_some_and(|r| r.offender == id) {
+            self.draw_four = None;
+        }
+
+        self.broadcast(PacketType::Disconnect {
+            id,
+            username: username.clone(),
+        });
+        self.broadcast(PacketType::Message {
+            sender: "Server".to_string(),
+            content: format!("{} {}", username, reason),
+        });
+        self.assign_host();
+
+        // Bots don't play on their own
+        if self.players.len() < 2 || self.players.keys().iter().all(|id| self.is_bot(id)) {
+            self.end();
+            return;
+        }
+
+        if self.current_turn == Some(id) {
+            self.swap_pending = false;
+            self.give_turn();
+        } else {
+            // The player after the current one may have changed
+            self.broadcast(PacketType::TurnUpdate {
+                current: self.current_turn.unwrap(),
+                next: self.players.predict_next(self.reversed),
+                deadline: self.turn_deadline,
+            });
+        }
+    }
+
+    // Returns a boolean indicating weather the current player is allowed to end their turn
+    fn can_end_turn(&self, id: &Uuid) -> bool {
         let draw_cards = [Type::DrawTwo, Type::DrawFour];
-        let last_card = self.placed_deck.get(0).unwrap();
 
-        // Check if the player can end their turn => allow in the case of the last card was a draw-card
-        if !(self
-            .players
-            .get(&self.current_turn.unwrap())
-            .unwrap()
-            .can_end()
-            || draw_cards.contains(&last_card.r#type) && last_card.owner.is_some())
-        {
-            self.emit(
+        // The last drawn card is always at the end of the hand
+        let drew_playable = self
+            .get_player(id)
+            .cards
+            .last()
+            .is_some_and(|card| self.allowed_cards(id).contains(card));
+        let last_card = self.placed_deck.front().unwrap();
+
+        // Allow in the case of the last card was a draw-card or there are no cards left to draw
+        self.get_player(id)
+            .can_end(self.options.draw_rule, drew_playable)
+            || draw_cards.contains(&last_card.r#type) && last_card.owner.is_some()
+            || self.is_deadlocked()
+    }
+
+    fn end_turn(&mut self, id: Uuid) {
+        let draw_cards = [Type::DrawTwo, Type::DrawFour];
+        let current = self.current_turn.unwrap();
+
+        if self.swap_pending {
+            self.error(
                 &id,
-                &to_json(PacketType::Error(
-                    401,
-                    "Cannot end your turn yet. Please either place a card or draw three cards"
-                        .to_string(),
-                )),
+                GameError::CannotEndTurn(
+                    "Choose a player to swap hands with before ending your turn".to_string(),
+                ),
             );
             return;
         }
 
+        // Check if the player can end their turn
+        if !self.can_end_turn(&current) {
+            let message = match self.options.draw_rule {
+                DrawRule::DrawThree => {
+                    "Cannot end your turn yet. Please either place a card or draw three cards"
+                }
+                DrawRule::UntilPlayable => {
+                    "Cannot end your turn yet. Please either place a card or draw until you get a playable card"
+                }
+            };
+            self.error(&id, GameError::CannotEndTurn(message.to_string()));
+            return;
+        }
+
+        let last_card = self.placed_deck.front().unwrap();
+
         // Drawing cards
         if last_card.owner != self.current_turn
             && last_card.owner.is_some()
@@ -356,17 +1465,17 @@ impl Game {
                 count = self.draw_stack;
             }
 
-            self.draw_cards(count, self.current_turn.unwrap());
-            self.placed_deck.get_mut(0).unwrap().owner = None;
+            self.draw_cards(count, current);
+            self.placed_deck.front_mut().unwrap().owner = None;
         }
 
         // Last card will always be owned by the last person who placed it
-        if self.placed_deck.get(0).unwrap().owner.is_some() {
-            self.placed_deck.get_mut(0).unwrap().owner = self.current_turn;
+        if self.placed_deck.front().unwrap().owner.is_some() {
+            self.placed_deck.front_mut().unwrap().owner = self.current_turn;
         }
 
         // Reversing
-        if self.placed_deck.get(0).unwrap().r#type == Type::Reverse {
+        if self.placed_deck.front().unwrap().r#type == Type::Reverse {
             self.reversed = !self.reversed;
 
             // Only give the turn back to the player if there's less than 3 players
@@ -374,11 +1483,11 @@ impl Game {
                 self.players.next_player(self.reversed);
             }
 
-            self.placed_deck.get_mut(0).unwrap().owner = None;
+            self.placed_deck.front_mut().unwrap().owner = None;
         }
 
         // Blocking
-        if self.placed_deck.get(0).unwrap().r#type == Type::Block {
+        if self.placed_deck.front().unwrap().r#type == Type::Block {
             let count = if self.block_stack > 1 {
                 self.block_stack
             } else {
@@ -389,31 +1498,36 @@ impl Game {
                 self.players.next_player(self.reversed);
             }
             // Reset block-stack and allow the same player to place cards by deowning the block-card.
-            self.placed_deck.get_mut(0).unwrap().owner = None;
+            self.placed_deck.front_mut().unwrap().owner = None;
             self.block_stack = 0;
         }
+
+        // Rotating hands, unless the player just placed their last card
+        if self.options.seven_zero
+            && self.placed_deck.front().unwrap().r#type == Type::Zero
+            && self.placed_deck.front().unwrap().owner.is_some()
+            && !self.get_player(&current).cards.is_empty()
+        {
+            self.rotate_hands();
+            self.placed_deck.front_mut().unwrap().owner = None;
+        }
+        self.finish_turn(current);
+    }
+
+    // Passes the turn to the next player, or ends the game if the player has no cards left
+    fn finish_turn(&mut self, current: Uuid) {
         // Clear all the actions done by the player during this turn
-        self.players
-            .get_mut(&self.current_turn.unwrap())
-            .unwrap()
-            .actions
-            .clear();
+        self.players.get_mut(&current).unwrap().actions.clear();
 
         // Send back the 'EndTurnPacket' to client to indicate their turn has ended
-        self.emit(&self.current_turn.unwrap(), &to_json(PacketType::EndTurn));
+        self.emit(&current, PacketType::EndTurn);
 
         // Update the status
-        self.update_card_status(&self.current_turn.unwrap());
+        self.update_card_status(&current);
 
         // There is no cards left => Player wins
-        if self
-            .players
-            .get_mut(&self.current_turn.unwrap())
-            .unwrap()
-            .cards
-            .is_empty()
-        {
-            self.end();
+        if self.players.get(&current).unwrap().cards.is_empty() {
+            self.end_round();
             return;
         }
 
@@ -421,80 +1535,106 @@ impl Game {
         self.give_turn();
     }
 
-    pub fn update_card_status(&self, self_id: &Uuid) {
-        for id in self.players.keys() {
-            if self_id == id {
-                /*
-                let p: PrivateGamePacket = PrivateGamePacket::new(
-                    self.players.get(id).unwrap().cards.clone(),
-                    self.placed_deck.get(0).unwrap().clone(),
-                );
-                */
-                self.emit(
-                    id,
-                    &to_json(PacketType::StatusUpdatePrivate(
-                        self.players.get(id).unwrap().cards.clone(),
-                        self.placed_deck.get(0).unwrap().clone(),
-                    )),
-                );
-            } else {
-                /*
-                let p: PublicGamePacket = PublicGamePacket::new(
-                    self_id.to_owned(),
-                    &self.players.get(self_id).unwrap().username,
-                    self.players.get(self_id).unwrap().cards.len(),
-                    self.placed_deck.get(0).unwrap().clone(),
-                );
-                */
-                self.emit(
-                    id,
-                    &to_json(PacketType::StatusUpdatePublic(
-                        self_id.to_owned(),
-                        self.players.get(self_id).unwrap().username.clone(),
-                        self.players.get(self_id).unwrap().cards.len(),
-                        self.placed_deck.get(0).unwrap().clone(),
-                    )),
-                );
-            }
-        }
+    pub fn update_card_status(&mut self, self_id: &Uuid) {
+        let player = self.players.get(self_id).unwrap();
+        let top = self.placed_deck.front().unwrap().clone();
+
+        let private = PacketType::StatusUpdatePrivate {
+            cards: player.cards.clone(),
+            top: top.clone(),
+        };
+        let public = PacketType::StatusUpdatePublic {
+            id: self_id.to_owned(),
+            username: player.username.clone(),
+            cards: player.cards.len(),
+            top,
+        };
+
+        self.emit(self_id, private);
+        self.broadcast_ignore_self(*self_id, public);
     }
 
     pub fn update_allowed_status(&mut self, self_id: &Uuid) {
-        println!("Updating allowed status");
+        let allowed = self.allowed_cards(self_id);
 
-        let placed_deck = self.placed_deck.clone();
-
-        let p = self.get_player(self_id);
+        self.emit(self_id, PacketType::AllowedCardsUpdate { cards: allowed });
+    }
 
-        let allowed: Vec<Card> = Card::get_allowed_cards(
-            placed_deck.get(0).unwrap().clone(),
-            p.cards.clone(),
+    // Returns the cards the player is allowed to place on top of the current card
+    pub fn allowed_cards(&self, id: &Uuid) -> Vec<Card> {
+        Card::get_allowed_cards(
+            self.placed_deck.front().unwrap().clone(),
+            self.get_player(id).cards.clone(),
             self.current_turn.unwrap(),
-        );
+            &self.options,
+        )
+    }
+
+    // Shuffles the placed cards, except the top one, back to the deck. Returns false if there was nothing to recycle.
+    fn recycle_deck(&mut self) -> bool {
+        if self.placed_deck.len() <= 1 {
+            return false;
+        }
 
-        self.send_message(&to_json(PacketType::AllowedCardsUpdate(allowed)), self_id);
+        let top = self.placed_deck.pop_front().unwrap();
+        let mut cards: Vec<Card> = self.placed_deck.drain(..).collect();
+
+        cards.iter_mut().for_each(|card| card.reset());
+        cards.shuffle(&mut self.rng);
+
+        self.deck.extend(cards);
+        self.placed_deck.push_front(top);
+        self.statistics.deck_recycled();
+
+        self.broadcast(PacketType::DeckReshuffled {
+            deck_size: self.deck.len(),
+        });
+        true
     }
 
-    pub fn draw_cards(&mut self, count: usize, owner: Uuid) {
-        let mut l: Vec<Card> = Vec::new();
+    // Returns a boolean indicating weather both the deck and the placed cards are exhausted
+    pub fn is_deadlocked(&self) -> bool {
+        self.deck.is_empty() && self.placed_deck.len() <= 1
+    }
+
+    fn draw_cards(&mut self, count: usize, owner: Uuid) {
+        self.deal_cards(count, owner);
+
+        // Every card counts towards the cards the player may draw this turn
         let p = self.players.get_mut(&owner).unwrap();
+        p.actions.extend(vec![Actions::DrawCard; count]);
+    }
+
+    // Gives cards from the deck to the player without counting it as an action of their turn
+    fn deal_cards(&mut self, count: usize, owner: Uuid) {
+        let mut l: Vec<Card> = Vec::new();
 
         for _ in 0..count {
-            if self.deck.is_empty() {
-                self.deck.extend(Card::generate_deck());
+            if self.deck.is_empty() && !self.recycle_deck() {
+                self.statistics.deadlocked = true;
+                self.broadcast(PacketType::Deadlock);
+                break;
             }
 
-            // l.push(self.deck.pop_front().unwrap());
-            l.push(Card::new(Type::Five, Color::Red));
+            l.push(self.deck.pop_front().unwrap());
 
             self.statistics.card_drawn();
         }
 
         l.iter_mut().for_each(|card| card.owner = Some(owner));
+
+        let p = self.players.get_mut(&owner).unwrap();
         p.cards.extend(l);
+        p.declared_uno = false;
+    }
 
-        // Puch the action to the actions list
-        p.actions.push(Actions::DrawCard);
+    // Puts the given cards on top of the deck in order. The start card is drawn first and the players are
+    // dealt in the order they joined, allowing tests to force specific hands.
+    #[cfg(feature = "test-utils")]
+    pub fn stack_deck(&mut self, cards: Vec<Card>) {
+        for card in cards.into_iter().rev() {
+            self.deck.push_front(card);
+        }
     }
 
     pub fn next_turn(&mut self) -> Uuid {
@@ -502,19 +1642,17 @@ impl Game {
         self.current_turn.unwrap()
     }
 
-    pub fn place_card(&mut self, index: usize, id: Uuid) {
+    fn place_card(&mut self, index: usize, id: Uuid) {
         let draw_cards = [Type::DrawTwo, Type::DrawFour];
         let p = self.players.get(&id).unwrap();
+        let top = self.placed_deck.front().unwrap();
 
         // Stacked draw-cards
-        if self.placed_deck.get(0).unwrap().r#type == p.cards.get(index).unwrap().r#type
-            && draw_cards.contains(&self.placed_deck.get(0).unwrap().r#type)
+        if self.options.draw_stacking
+            && top.r#type == p.cards.get(index).unwrap().r#type
+            && draw_cards.contains(&top.r#type)
         {
-            let count = if self.placed_deck.get(0).unwrap().r#type == Type::DrawFour {
-                4
-            } else {
-                2
-            };
+            let count = if top.r#type == Type::DrawFour { 4 } else { 2 };
             self.draw_stack += if self.draw_stack == 0 {
                 count * 2
             } else {
@@ -525,15 +1663,15 @@ impl Game {
         }
 
         // Stacked block-cards
-        if self.placed_deck.get(0).unwrap().r#type == p.cards.get(index).unwrap().r#type
-            && self.placed_deck.get(0).unwrap().r#type == Type::Block
-            && p.cards.get(index).unwrap().owner == self.placed_deck.get(0).unwrap().owner
+        if self.options.block_stacking
+            && top.r#type == p.cards.get(index).unwrap().r#type
+            && top.r#type == Type::Block
+            && p.cards.get(index).unwrap().owner == top.owner
         {
             self.block_stack += if self.block_stack == 0 { 2 } else { 1 };
         } else {
             self.block_stack = 0;
         }
-        println!("block stack is now the size of {}", self.block_stack);
 
         //Shadowing player now when we need it mutable
         let p = self.players.get_mut(&id).unwrap();
@@ -541,29 +1679,241 @@ impl Game {
         // Puch the action to the actions list
         p.actions.push(Actions::PlaceCard);
 
-        self.placed_deck
-            .push_front(p.cards.get(index).unwrap().clone());
-        p.cards.remove(index);
+        // Remember the hand and the color a draw-four was placed on in case it gets challenged
+        self.draw_four = match p.cards[index].r#type {
+            Type::DrawFour => Some(DrawFourRecord {
+                offender: id,
+                hand: p.cards.clone(),
+                prior_color: top.color.clone(),
+            }),
+            _ => None,
+        };
+
+        let card = p.cards.remove(index);
+
+        // The player has to choose who to swap hands with, unless it was their last card
+        if self.options.seven_zero && card.r#type == Type::Seven && !p.cards.is_empty() {
+            self.swap_pending = true;
+        }
+
+        self.placed_deck.push_front(card);
 
         self.statistics.card_placed();
     }
 
-    pub fn switch_color(&mut self, color: Color) {
-        let allowed_types = vec![Type::DrawFour, Type::Switch];
+    fn call_uno(&mut self, id: Uuid) {
+        let p = self.get_player(&id);
+
+        // Uno can be called with the last card, or right before placing the second to last one
+        let allowed = p.cards.len() == 1 || p.cards.len() == 2 && self.current_turn == Some(id);
+
+        if !self.active || !allowed {
+            self.error(
+                &id,
+                GameError::IllegalAction("You cannot call uno right now".to_string()),
+            );
+            return;
+        }
+
+        self.players.get_mut(&id).unwrap().declared_uno = true;
+        self.broadcast(PacketType::UnoCalled { id });
+    }
+
+    // Players with one undeclared card can be caught until their turn ends
+    fn catch_uno(&mut self, id: Uuid, target: Uuid) {
+        let catchable = self.players.get(&target).is_some_and(|p| {
+            p.cards.len() == 1 && !p.declared_uno && self.current_turn == Some(target)
+        });
+
+        if !self.active || id == target || !catchable {
+            self.error(
+                &id,
+                GameError::IllegalAction("That player cannot be caught".to_string()),
+            );
+            return;
+        }
+
+        let penalty = self.options.uno_penalty;
+        self.deal_cards(penalty, target);
+
+        self.broadcast(PacketType::UnoCaught {
+            catcher: id,
+            target,
+            penalty,
+        });
+        self.update_card_status(&target);
+        self.update_allowed_status(&target);
+    }
+
+    // The player next to a draw-four can challenge it instead of drawing. If the offender had a card
+    // matching the previous color they draw the cards instead, otherwise the challenger draws two extra.
+    fn challenge_draw_four(&mut self, id: Uuid) {
+        let wild_cards = [Type::Switch, Type::DrawFour];
+        let top = self.placed_deck.front().unwrap();
+
+        let record = match &self.draw_four {
+            Some(record)
+                if top.r#type == Type::DrawFour
+                    && top.owner == Some(record.offender)
+                    && record.offender != id
+                    && self.get_player(&id).actions.is_empty() =>
+            {
+                record.clone()
+            }
+            _ => {
+                self.error(
+                    &id,
+                    GameError::IllegalAction("There is no draw-four to challenge".to_string()),
+                );
+                return;
+            }
+        };
+
+        let guilty = record
+            .hand
+            .iter()
+            .any(|card| card.color == record.prior_color && !wild_cards.contains(&card.r#type));
+        let count = self.draw_stack.max(4);
+
+        self.draw_four = None;
+        self.draw_stack = 0;
+        self.placed_deck.front_mut().unwrap().owner = None;
+
+        self.emit(
+            &id,
+            PacketType::ChallengeReveal {
+                offender: record.offender,
+                cards: record.hand.clone(),
+            },
+        );
+        self.broadcast(PacketType::ChallengeResult {
+            challenger: id,
+            offender: record.offender,
+            successful: guilty,
+        });
+
+        if guilty {
+            // The challenger continues their turn as normal
+            self.deal_cards(count, record.offender);
+            self.update_card_status(&record.offender);
+            self.update_allowed_status(&id);
+        } else {
+            self.deal_cards(count + 2, id);
+            self.finish_turn(id);
+        }
+    }
+
+    // Returns a boolean indicating weather the player can place the card out of turn
+    fn can_jump_in(&self, id: &Uuid, index: usize) -> bool {
+        let disallowed_types = [Type::DrawTwo, Type::DrawFour, Type::Switch];
+
+        if !self.options.jump_in || !self.active || self.current_turn == Some(*id) {
+            return false;
+        }
+
+        let top = self.placed_deck.front().unwrap();
+
+        self.get_player(id).cards.get(index).is_some_and(|card| {
+            card.r#type == top.r#type
+                && card.color == top.color
+                && !disallowed_types.contains(&card.r#type)
+        })
+    }
+
+    fn jump_in(&mut self, id: Uuid) {
+        let interrupted = self.current_turn.unwrap();
+
+        self.players.get_mut(&interrupted).unwrap().actions.clear();
+        self.swap_pending = false;
+        self.emit(&interrupted, PacketType::EndTurn);
+
+        // Rotate the players until the one jumping in is the current one
+        while self.next_turn() != id {}
+        let deadline = self.start_turn_timer();
+
+        self.broadcast(PacketType::Message {
+            sender: "Server".to_string(),
+            content: format!("{} jumped in", self.get_player(&id).username),
+        });
+        self.broadcast(PacketType::TurnUpdate {
+            current: id,
+            next: self.players.predict_next(self.reversed),
+            deadline,
+        });
+    }
+
+    fn swap_hands(&mut self, id: Uuid, target: Uuid) {
+        if !self.swap_pending || id == target || !self.players.contains_key(&target) {
+            self.error(
+                &id,
+                GameError::IllegalAction("Cannot swap hands with that player".to_string()),
+            );
+            return;
+        }
+
+        let mut cards = std::mem::take(&mut self.players.get_mut(&id).unwrap().cards);
+        let mut target_cards = std::mem::take(&mut self.players.get_mut(&target).unwrap().cards);
+
+        cards.iter_mut().for_each(|card| card.owner = Some(target));
+        target_cards
+            .iter_mut()
+            .for_each(|card| card.owner = Some(id));
+
+        self.players.get_mut(&id).unwrap().cards = target_cards;
+        self.players.get_mut(&target).unwrap().cards = cards;
+        self.swap_pending = false;
+
+        self.broadcast(PacketType::Message {
+            sender: "Server".to_string(),
+            content: format!(
+                "{} swapped hands with {}",
+                self.get_player(&id).username,
+                self.get_player(&target).username
+            ),
+        });
+
+        self.update_card_status(&id);
+        self.update_card_status(&target);
+    }
+
+    // Passes every hand to the next player in the direction of play
+    fn rotate_hands(&mut self) {
+        let order = self.players.turn_order(self.reversed);
+
+        let mut hands: Vec<Vec<Card>> = order
+            .iter()
+            .map(|id| std::mem::take(&mut self.players.get_mut(id).unwrap().cards))
+            .collect();
+        hands.rotate_right(1);
+
+        for (id, mut hand) in order.iter().zip(hands) {
+            hand.iter_mut().for_each(|card| card.owner = Some(*id));
+            self.players.get_mut(id).unwrap().cards = hand;
+        }
+
+        self.broadcast(PacketType::Message {
+            sender: "Server".to_string(),
+            content: "Hands were passed to the next player".to_string(),
+        });
 
-        if allowed_types.contains(&self.placed_deck.get(0).unwrap().r#type) {
-            println!("{:#?}", &color);
-            let c = self.placed_deck.get(0).unwrap().clone();
+        for id in order {
+            self.update_card_status(&id);
+        }
+    }
 
-            self.broadcast(&to_json(PacketType::Message(
-                "Server".to_string(),
-                format!("Switched color to {}", color),
-            )));
+    fn switch_color(&mut self, color: Color) {
+        let allowed_types = [Type::DrawFour, Type::Switch];
 
-            self.placed_deck
-                .insert(0, Card::new_with_owner(c.r#type, color, c.owner));
+        if allowed_types.contains(&self.placed_deck.front().unwrap().r#type) {
+            self.broadcast(PacketType::Message {
+                sender: "Server".to_string(),
+                content: format!("Switched color to {}", color),
+            });
 
-            println!("{:#?}", self.placed_deck.get(0));
+            // Remember the printed color so that the card can be reset when the deck is recycled
+            let card = self.placed_deck.front_mut().unwrap();
+            card.base_color.get_or_insert(card.color.clone());
+            card.color = color;
         }
     }
 }
@@ -577,42 +1927,55 @@ impl Default for Game {
 #[derive(Debug, Clone)]
 pub struct Player {
     pub id: Uuid,
-    pub socket: Socket,
     pub username: String,
     pub is_connected: bool,
     pub is_host: bool,
     pub cards: Vec<Card>,
     pub waiting: bool,
+    // The player has called uno for their last card
+    pub declared_uno: bool,
+    // The player has let their turn time out repeatedly
+    pub afk: bool,
+    // Incremented every time the player loses connection, used to tell grace periods apart
+    pub disconnects: usize,
+    timeouts: usize,
     actions: Vec<Actions>,
 }
 
 impl Player {
-    pub fn new(id: Uuid, socket: &Socket) -> Player {
+    pub fn new(id: Uuid) -> Player {
         Player {
             id,
-            socket: socket.to_owned(),
             username: String::from("connecting..."),
             is_host: false,
             is_connected: false,
             cards: Vec::new(),
             waiting: false,
+            declared_uno: false,
+            afk: false,
+            disconnects: 0,
+            timeouts: 0,
             actions: Vec::new(),
         }
     }
 
-    pub fn can_end(&self) -> bool {
-        // Player can end their turn only if they have placed one card or drawn 3 cards
+    pub fn can_end(&self, draw_rule: DrawRule, drew_playable: bool) -> bool {
+        let drawn = self
+            .actions
+            .iter()
+            .filter(|a| **a == Actions::DrawCard)
+            .count();
+
+        // Player can end their turn only if they have placed one card or drawn enough cards
         self.actions
             .iter()
             .filter(|a| **a == Actions::PlaceCard)
             .count()
             >= 1
-            || self
-                .actions
-                .iter()
-                .filter(|a| **a == Actions::DrawCard)
-                .count()
-                >= 3
+            || match draw_rule {
+                DrawRule::DrawThree => drawn >= 3,
+                DrawRule::UntilPlayable => drawn >= 1 && drew_playable,
+            }
     }
 }
 
@@ -622,14 +1985,16 @@ pub enum Actions {
     PlaceCard,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
 pub struct Card {
     pub r#type: Type,
     pub color: Color,
     pub owner: Option<Uuid>,
+    #[serde(skip)]
+    base_color: Option<Color>,
 }
 
-#[derive(strum_macros::Display, Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(strum_macros::Display, Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
 pub enum Color {
     Red,
     Blue,
@@ -643,7 +2008,7 @@ impl Color {
     }
 }
 
-#[derive(strum_macros::Display, Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(strum_macros::Display, Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
 pub enum Type {
     Zero,
     One,
@@ -685,23 +2050,47 @@ impl Type {
 }
 
 impl Card {
-    fn new(r#type: Type, color: Color) -> Card {
+    // Amount of cards in a full deck
+    pub fn deck_size() -> usize {
+        Color::iter().len() * Type::iter().len() * 2
+    }
+
+    pub fn new(r#type: Type, color: Color) -> Card {
         Card {
             r#type,
             color,
             owner: None,
+            base_color: None,
         }
     }
 
-    fn new_with_owner(r#type: Type, color: Color, owner: Option<Uuid>) -> Card {
-        Card {
-            r#type,
-            color,
-            owner,
+    // Returns the official point value of the card
+    pub fn points(&self) -> usize {
+        match self.r#type {
+            Type::Zero => 0,
+            Type::One => 1,
+            Type::Two => 2,
+            Type::Three => 3,
+            Type::Four => 4,
+            Type::Five => 5,
+            Type::Six => 6,
+            Type::Seven => 7,
+            Type::Eight => 8,
+            Type::Nine => 9,
+            Type::Block | Type::Reverse | Type::DrawTwo => 20,
+            Type::Switch | Type::DrawFour => 50,
+        }
+    }
+
+    // Restores the card to the state it was in the deck
+    fn reset(&mut self) {
+        if let Some(color) = self.base_color.take() {
+            self.color = color;
         }
+        self.owner = None;
     }
 
-    fn generate_deck() -> VecDeque<Card> {
+    fn generate_deck(rng: &mut StdRng) -> VecDeque<Card> {
         let mut l: Vec<Card> = Vec::new();
 
         for c in &Color::iter() {
@@ -710,38 +2099,56 @@ impl Card {
                 l.push(Card::new(t.clone(), c.clone()));
             }
         }
-        l.shuffle(&mut thread_rng());
+        l.shuffle(rng);
         VecDeque::from(l)
     }
 
-    fn get_allowed_start_card(deck: &VecDeque<Card>) -> Card {
-        let disallowed_types = vec![
+    // Takes the first non-action card from the deck. Rejected cards are returned to the bottom of the deck.
+    fn get_allowed_start_card(deck: &mut VecDeque<Card>) -> Card {
+        let disallowed_types = [
             Type::Block,
             Type::Switch,
             Type::DrawFour,
             Type::Reverse,
             Type::DrawTwo,
         ];
-        /*
-        deck.iter()
-            .filter(|card| !disallowed_types.contains(&card.r#type))
-            .collect::<VecDeque<&Card>>()
-            .pop_back()
-            .unwrap()
-            .clone()
-        */
-        Card::new(Type::Five, Color::Red)
-    }
-
-    fn get_allowed_cards(last_card: Card, deck: Vec<Card>, owner: Uuid) -> Vec<Card> {
+        let mut rejected: Vec<Card> = Vec::new();
+
+        let card = loop {
+            let card = deck.pop_front().unwrap();
+
+            if !disallowed_types.contains(&card.r#type) {
+                break card;
+            }
+            rejected.push(card);
+        };
+
+        deck.extend(rejected);
+        card
+    }
+
+    pub fn get_allowed_cards(
+        last_card: Card,
+        deck: Vec<Card>,
+        owner: Uuid,
+        options: &GameOptions,
+    ) -> Vec<Card> {
         let mut l = Vec::new();
         let special = [Type::Switch, Type::DrawFour];
         let draw_cards = [Type::DrawTwo, Type::DrawFour];
 
         for card in deck {
             if last_card.owner == Some(owner) && last_card.owner.is_some() {
+                let stacking = if draw_cards.contains(&card.r#type) {
+                    options.draw_stacking
+                } else if card.r#type == Type::Block {
+                    options.block_stacking
+                } else {
+                    true
+                };
+
                 // SAME TYPES
-                if card.r#type == last_card.r#type {
+                if card.r#type == last_card.r#type && stacking {
                     l.push(card);
                     continue;
                 }
@@ -765,8 +2172,8 @@ impl Card {
                         continue;
                     }
                 } else if draw_cards.contains(&last_card.r#type) && last_card.owner != Some(owner) {
-                    // SPECIAL CARDS
-                    if last_card.r#type == card.r#type {
+                    // STACKED DRAW-CARDS
+                    if options.draw_stacking && last_card.r#type == card.r#type {
                         l.push(card);
                         continue;
                     }
@@ -795,7 +2202,3 @@ impl Card {
         l
     }
 }
-
-pub fn to_json(data: PacketType) -> String {
-    serde_json::to_string(&data).unwrap()
-}
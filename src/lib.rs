@@ -1,7 +1,11 @@
+pub mod bot;
 pub mod errors;
 pub mod game;
 pub mod lobby;
+pub mod matchmaking;
 pub mod messages;
+pub mod options;
 pub mod packets;
+pub mod rooms;
 pub mod start_connection;
 pub mod ws;
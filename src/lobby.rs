@@ -1,24 +1,636 @@
-use crate::game::{Game, Player};
-use crate::messages::{Connect, Disconnect, Packet, WsMessage};
+use crate::errors::{GameError, OptionsError};
+use crate::game::{Action, Card, Event, Game, Target, MAX_PLAYERS};
+use crate::messages::{
+    Connect, CreateRoom, DeleteRoom, Dequeue, Disconnect, Encoding, Enqueue, GetRoom, InviteToRoom,
+    ListRooms, Packet, WsMessage,
+};
+use crate::options::GameOptions;
 use crate::packets::*;
-use actix::prelude::{Actor, Context, Handler};
-use serde_json::Result;
-use std::collections::HashMap;
+use actix::prelude::{Actor, AsyncContext, Context, Handler, MessageResult, Recipient};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
-#[derive(Debug, Default)]
+type Socket = Recipient<WsMessage>;
+
+// Time a disconnected player has to resume their session before their seat is given up
+const RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+// Recent events kept around for every connection to replay them to clients that missed some
+const HISTORY_SIZE: usize = 256;
+const INVITE_LENGTH: usize = 8;
+// Time a room created ahead of its players is kept around without anyone connecting to it
+const ROOM_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug)]
 pub struct Lobby {
     rooms: HashMap<Uuid, Room>,
+    // Matchmaking connections waiting for other players, in the order they joined
+    queue: Vec<Ticket>,
+    room_expiry: Duration,
+    grace_period: Duration,
+}
+
+impl Lobby {
+    pub fn new(room_expiry: Duration, grace_period: Duration) -> Lobby {
+        Lobby {
+            rooms: HashMap::new(),
+            queue: Vec::new(),
+            room_expiry,
+            grace_period,
+        }
+    }
+}
+
+impl Default for Lobby {
+    fn default() -> Self {
+        Lobby::new(ROOM_EXPIRY, RECONNECT_GRACE_PERIOD)
+    }
+}
+
+#[derive(Debug)]
+struct Ticket {
+    id: Uuid,
+    addr: Socket,
+    players: usize,
+    options: GameOptions,
+}
+
+// Options of a new room, sent to 'POST /rooms' or as query parameters by the first connection
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RoomOptions {
+    pub seed: Option<u64>,
+    pub max_players: Option<usize>,
+    pub password: Option<String>,
+    // Only connections with an invite of the host can join
+    pub invite_only: bool,
+    pub public: bool,
+    // House rules used when 'StartGame' doesn't set any
+    pub options: GameOptions,
+}
+
+// Query parameters of a connection to an existing room, any of them lets it in
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Credentials {
+    pub password: Option<String>,
+    // Single-use invite code
+    pub invite: Option<String>,
+    // Resume-token of a player coming back to the room
+    pub session: Option<Uuid>,
+}
+
+impl RoomOptions {
+    pub fn check(&self) -> Result<(), GameError> {
+        if let Some(max) = self
+            .max_players
+            .filter(|max| !(2..=MAX_PLAYERS).contains(max))
+        {
+            return Err(OptionsError::MaxPlayers(max).into());
+        }
+
+        Ok(self.options.check()?)
+    }
 }
 
 #[derive(Debug)]
 pub struct Room {
+    id: Uuid,
     game: Game,
+    sockets: HashMap<Uuid, Socket>,
+    // Sockets that negotiated a format other than JSON
+    encodings: HashMap<Uuid, Encoding>,
+    // Sockets that announced a supported protocol version with 'Hello'
+    greeted: HashSet<Uuid>,
+    // Resume-token -> player
+    tokens: HashMap<Uuid, Uuid>,
+    // Connection -> player, for connections that resumed the session of a player
+    aliases: HashMap<Uuid, Uuid>,
+    // Turn of the game the latest turn timer was scheduled for
+    timer_turn: usize,
+    // Sequence number of the latest event sent to each connection. Every connection has its own
+    // counter, so a gap in the numbers it receives always means that events were lost.
+    seqs: HashMap<Uuid, u64>,
+    history: HashMap<Uuid, VecDeque<(u64, PacketType)>>,
+    password: Option<String>,
+    invite_only: bool,
+    // Unused invite codes
+    invites: HashSet<String>,
+    public: bool,
+    // Token needed to delete the room, only set for rooms created through the REST api
+    owner: Option<Uuid>,
+    // Player -> address of their connection
+    addresses: HashMap<Uuid, IpAddr>,
+    // Addresses and resume-tokens of the banned players, kept for as long as the room exists
+    banned_addresses: HashSet<IpAddr>,
+    banned: HashSet<Uuid>,
 }
 
 impl Room {
-    fn new() -> Room {
-        Room { game: Game::new() }
+    fn new(id: Uuid, options: &RoomOptions) -> Room {
+        let mut game = match options.seed {
+            Some(seed) => Game::with_seed(seed),
+            None => Game::new(),
+        };
+        game.max_players = options.max_players.unwrap_or(MAX_PLAYERS);
+        game.options = options.options.clone();
+
+        Room {
+            id,
+            game,
+            sockets: HashMap::new(),
+            encodings: HashMap::new(),
+            greeted: HashSet::new(),
+            tokens: HashMap::new(),
+            aliases: HashMap::new(),
+            timer_turn: 0,
+            seqs: HashMap::new(),
+            history: HashMap::new(),
+            password: options.password.clone(),
+            invite_only: options.invite_only,
+            invites: HashSet::new(),
+            public: options.public,
+            owner: None,
+            addresses: HashMap::new(),
+            banned_addresses: HashSet::new(),
+            banned: HashSet::new(),
+        }
+    }
+
+    // Returns true if a connection can join the room and take a seat right away
+    fn is_open(&self) -> bool {
+        !self.game.active && !self.is_locked() && self.game.players.len() < self.game.max_players
+    }
+
+    // Returns true if nobody has joined the room yet
+    fn is_unused(&self) -> bool {
+        self.sockets.is_empty() && self.game.players.is_empty() && self.game.spectators.is_empty()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.password.is_some() || self.invite_only
+    }
+
+    // Checks the credentials of a connection to the room, using up its invite if that's what let it in
+    fn admit(&mut self, credentials: &Credentials, ip: Option<IpAddr>) -> Result<(), GameError> {
+        if ip.is_some_and(|ip| self.banned_addresses.contains(&ip))
+            || credentials
+                .session
+                .is_some_and(|token| self.banned.contains(&token))
+        {
+            return Err(GameError::Banned);
+        }
+
+        if credentials
+            .session
+            .is_some_and(|token| self.tokens.contains_key(&token))
+        {
+            return Ok(());
+        }
+
+        if let Some(invite) = &credentials.invite {
+            if !self.invites.remove(invite) {
+                return Err(GameError::InvalidInvite);
+            }
+            return Ok(());
+        }
+
+        if self.invite_only {
+            return Err(GameError::InvalidInvite);
+        }
+        if self.password.is_some() && self.password != credentials.password {
+            return Err(GameError::WrongPassword);
+        }
+
+        Ok(())
+    }
+
+    // Creates a single-use invite and returns its code
+    fn invite(&mut self) -> String {
+        let code: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(INVITE_LENGTH)
+            .map(char::from)
+            .collect();
+
+        self.invites.insert(code.clone());
+        code
+    }
+
+    pub fn settings(&self) -> RoomSettings {
+        RoomSettings {
+            id: self.id,
+            active: self.game.active,
+            players: self.game.players.len(),
+            spectators: self.game.spectators.len(),
+            max_players: self.game.max_players,
+            public: self.public,
+            locked: self.is_locked(),
+            options: self.game.options.clone(),
+        }
+    }
+
+    // Encodes the packet in the format negotiated by the socket and sends it
+    fn send_message(&self, envelope: &Envelope, id: &Uuid) {
+        if let Some(socket_recipient) = self.sockets.get(id) {
+            let encoding = self.encodings.get(id).copied().unwrap_or_default();
+            socket_recipient.do_send(encoding.encode(envelope));
+        } else {
+            println!("Couldn't find anyone to send message to");
+        }
+    }
+
+    // Stamps the events produced by the game with the next sequence number of each recipient and
+    // delivers them to the matching sockets
+    fn dispatch(&mut self, events: Vec<Event>) {
+        for event in events {
+            for id in self.recipients(&event.target) {
+                let seq = self.seqs.entry(id).or_insert(0);
+                *seq += 1;
+
+                let data = Envelope {
+                    seq: *seq,
+                    packet: &event.packet,
+                };
+                self.send_message(&data, &id);
+
+                let history = self.history.entry(id).or_default();
+                history.push_back((data.seq, event.packet.clone()));
+                if history.len() > HISTORY_SIZE {
+                    history.pop_front();
+                }
+            }
+
+            if let PacketType::Kicked { id, banned } = event.packet {
+                self.remove_socket(id, banned);
+            }
+        }
+    }
+
+    // Returns the connected players and spectators the target is addressed to
+    fn recipients(&self, target: &Target) -> Vec<Uuid> {
+        self.sockets
+            .keys()
+            .filter(|id| match target {
+                Target::Player(player) => *player == **id,
+                Target::All => true,
+                Target::AllExcept(player) => *player != **id,
+                Target::Spectators => self.game.spectators.contains_key(id),
+            })
+            .copied()
+            .collect()
+    }
+
+    // Sends the events of the connection starting from 'from_seq' again, with their original
+    // sequence numbers. Falls back to a snapshot of the game if some of them are no longer kept around.
+    fn resync(&mut self, id: Uuid, from_seq: u64) -> Vec<Event> {
+        let Some(history) = self.history.get(&id) else {
+            return Vec::new();
+        };
+        if history
+            .front()
+            .is_some_and(|(first, _)| from_seq < *first && *first > 1)
+        {
+            return self.game.snapshot(id);
+        }
+
+        for (seq, packet) in history {
+            if *seq >= from_seq {
+                let data = Envelope { seq: *seq, packet };
+                self.send_message(&data, &id);
+            }
+        }
+        Vec::new()
+    }
+
+    // Answers the 'Hello' of a client, rejecting versions the server can't talk to.
+    // The 'Welcome' is already sent in the format the client asked for.
+    fn hello(&mut self, id: Uuid, version: u32, capabilities: &[String]) -> PacketType {
+        if !is_supported(version) {
+            return GameError::IncompatibleVersion(version).into();
+        }
+
+        if capabilities.iter().any(|c| c == "msgpack") {
+            self.encodings.insert(id, Encoding::MessagePack);
+        } else {
+            self.encodings.remove(&id);
+        }
+        self.greeted.insert(id);
+
+        PacketType::Welcome {
+            version: PROTOCOL_VERSION,
+            features: FEATURES.iter().map(|f| f.to_string()).collect(),
+            room: self.settings(),
+        }
+    }
+
+    // Closes the connection of a kicked player. The address and resume-token of a banned player are
+    // remembered so that neither can be used to come back.
+    fn remove_socket(&mut self, id: Uuid, banned: bool) {
+        if let Some(socket) = self.sockets.remove(&id) {
+            socket.do_send(WsMessage::Close);
+        }
+        self.encodings.remove(&id);
+        self.greeted.remove(&id);
+        self.seqs.remove(&id);
+        self.history.remove(&id);
+        self.aliases.retain(|_, player| *player != id);
+
+        if let Some(ip) = self.addresses.remove(&id).filter(|_| banned) {
+            self.banned_addresses.insert(ip);
+        }
+        let tokens = self.tokens.iter().filter(|(_, player)| **player == id);
+        if banned {
+            self.banned.extend(tokens.map(|(token, _)| *token));
+        }
+        self.tokens.retain(|_, player| *player != id);
+    }
+
+    // Closes the connection of a player, the socket is cleaned up once it has disconnected
+    fn close(&self, id: Uuid) {
+        if let Some(socket) = self.sockets.get(&id) {
+            socket.do_send(WsMessage::Close);
+        }
+    }
+
+    // Returns the player the connection is playing as
+    fn resolve(&self, conn: &Uuid) -> Uuid {
+        *self.aliases.get(conn).unwrap_or(conn)
+    }
+
+    // Returns true if the connection is the one currently used by its player
+    fn is_current(&self, conn: &Uuid) -> bool {
+        let id = self.resolve(conn);
+        self.aliases
+            .iter()
+            .find(|(_, player)| **player == id)
+            .map_or(*conn == id, |(alias, _)| alias == conn)
+    }
+
+    // Returns true if a disconnected player can still resume their session
+    fn has_pending_sessions(&self) -> bool {
+        self.tokens
+            .values()
+            .any(|id| self.game.players.get(id).is_some_and(|p| !p.is_connected))
+    }
+
+    // Issues a resume-token for a newly registered player
+    fn issue_token(&mut self, id: Uuid) -> Event {
+        let token = Uuid::new_v4();
+        self.tokens.insert(token, id);
+        Event::new(Target::Player(id), PacketType::Session { token })
+    }
+
+    // Lets a spectating connection take over the seat of a disconnected player
+    fn resume(&mut self, conn: Uuid, token: Uuid) -> Vec<Event> {
+        if self.banned.contains(&token) {
+            return vec![Event::new(Target::Player(conn), GameError::Banned.into())];
+        }
+
+        let id = match self.tokens.get(&token) {
+            Some(id)
+                if self.game.spectators.contains_key(&conn)
+                    && self.game.players.get(id).is_some_and(|p| !p.is_connected) =>
+            {
+                *id
+            }
+            _ => {
+                return vec![Event::new(
+                    Target::Player(conn),
+                    GameError::InvalidSession.into(),
+                )];
+            }
+        };
+
+        let socket = self.sockets.remove(&conn).unwrap();
+        let encoding = self.encodings.remove(&conn);
+        let greeted = self.greeted.remove(&conn);
+        let address = self.addresses.remove(&conn);
+        // The connection keeps its own sequence numbers
+        let seq = self.seqs.remove(&conn);
+        let history = self.history.remove(&conn);
+        self.game.spectators.remove(&conn);
+
+        // A player only has a single alias, the latest connection
+        self.aliases.retain(|_, player| *player != id);
+        if conn != id {
+            self.aliases.insert(conn, id);
+        }
+        self.sockets.insert(id, socket);
+        match encoding {
+            Some(encoding) => self.encodings.insert(id, encoding),
+            None => self.encodings.remove(&id),
+        };
+        match address {
+            Some(address) => self.addresses.insert(id, address),
+            None => self.addresses.remove(&id),
+        };
+        if greeted {
+            self.greeted.insert(id);
+        } else {
+            self.greeted.remove(&id);
+        }
+        match seq {
+            Some(seq) => self.seqs.insert(id, seq),
+            None => self.seqs.remove(&id),
+        };
+        match history {
+            Some(history) => self.history.insert(id, history),
+            None => self.history.remove(&id),
+        };
+
+        self.game.reconnect(id)
+    }
+
+    // Answers a packet sent by the connection of the given player
+    fn process(&mut self, id: Uuid, data: PacketType) -> Vec<Event> {
+        // Any connection can negotiate the protocol
+        if let PacketType::Hello {
+            version,
+            capabilities,
+        } = data
+        {
+            let packet = self.hello(id, version, &capabilities);
+            return vec![Event::new(Target::Player(id), packet)];
+        }
+
+        // Any connection can catch up on the events it missed
+        if let PacketType::Resync { from_seq } = data {
+            return self.resync(id, from_seq);
+        }
+
+        // Spectators are limited to their own chat, taking a seat and resuming a session
+        if self.game.spectators.contains_key(&id) {
+            return match data {
+                PacketType::Register { username } => self.game.register(id, &username),
+                PacketType::SpectatorMessage { sender, content } => vec![Event::new(
+                    Target::Spectators,
+                    PacketType::SpectatorMessage { sender, content },
+                )],
+                PacketType::TakeSeat => self.game.take_seat(id),
+                PacketType::Resume { token } => self.resume(id, token),
+                _ => vec![Event::new(
+                    Target::Player(id),
+                    GameError::IllegalAction(
+                        "Spectators can only chat, take a seat or resume a session".to_string(),
+                    )
+                    .into(),
+                )],
+            };
+        }
+
+        // Connections that are no longer part of the game can't do anything
+        if !self.game.players.contains_key(&id) {
+            return vec![Event::new(
+                Target::Player(id),
+                GameError::UnknownPlayer.into(),
+            )];
+        }
+
+        let name = data.to_string();
+        let action = match data {
+            PacketType::Register { username } => {
+                let registered = self.game.get_player(&id).is_connected;
+                let mut events = self.game.register(id, &username);
+
+                if !registered {
+                    events.push(self.issue_token(id));
+                }
+                return events;
+            }
+            PacketType::GameData { .. } => None, // Will only be sent to client
+            PacketType::Connect { .. } => None,  // Will only be sent to client
+            PacketType::Disconnect { .. } => None, // Will only be sent to client
+            PacketType::Message { sender, content } => {
+                return vec![Event::new(
+                    Target::All,
+                    PacketType::Message { sender, content },
+                )];
+            }
+            PacketType::StartGame { options } => {
+                let options = options.unwrap_or_else(|| self.game.options.clone());
+
+                match options.check() {
+                    Ok(()) => Some(Action::StartGame(options)),
+                    Err(e) => {
+                        return vec![Event::new(Target::Player(id), GameError::from(e).into())];
+                    }
+                }
+            }
+            PacketType::Hello { .. } => None,   // Handled above
+            PacketType::Welcome { .. } => None, // Will only be sent to client
+            PacketType::StatusUpdatePublic { .. } => None, // Will only be sent to client
+            PacketType::StatusUpdatePrivate { .. } => None, // Will only be sent to client
+            PacketType::AllowedCardsUpdate { .. } => None, // Will only be sent to client
+            PacketType::DrawCard { amount } => Some(Action::DrawCard(amount.into())),
+            PacketType::PlaceCard { index } => Some(Action::PlaceCard(index)),
+            PacketType::EndTurn => Some(Action::EndTurn),
+            PacketType::ColorSwitch { color } => Some(Action::ColorSwitch(color)),
+            PacketType::TurnUpdate { .. } => None, // Will only be sent to client
+            PacketType::Error { .. } => None,
+            PacketType::Ack { .. } => None, // Will only be sent to client
+            PacketType::WinUpdate { .. } => None, // Will only be sent to client
+            PacketType::DeckReshuffled { .. } => None, // Will only be sent to client
+            PacketType::Deadlock => None,   // Will only be sent to client
+            PacketType::SwapHands { target } => Some(Action::SwapHands(target)),
+            PacketType::CallUno => Some(Action::CallUno),
+            PacketType::CatchUno { target } => Some(Action::CatchUno(target)),
+            PacketType::UnoCalled { .. } => None, // Will only be sent to client
+            PacketType::UnoCaught { .. } => None, // Will only be sent to client
+            PacketType::ChallengeDrawFour => Some(Action::ChallengeDrawFour),
+            PacketType::ChallengeResult { .. } => None, // Will only be sent to client
+            PacketType::ChallengeReveal { .. } => None, // Will only be sent to client
+            PacketType::MatchUpdate { .. } => None,     // Will only be sent to client
+            PacketType::PlayerAfk { .. } => None,       // Will only be sent to client
+            PacketType::Session { .. } => None,         // Will only be sent to client
+            PacketType::Resume { .. } => None,          // Only sent by spectators
+            PacketType::ConnectionUpdate { .. } => None, // Will only be sent to client
+            PacketType::SpectatorMessage { .. } => None, // Only sent by spectators
+            PacketType::TakeSeat => None,               // Only sent by spectators
+            PacketType::AddBot { strategy } => Some(Action::AddBot(strategy)),
+            PacketType::RemoveBot { id: bot } => Some(Action::RemoveBot(bot)),
+            PacketType::Kick { id: target } => Some(Action::Kick(target)),
+            PacketType::Ban { id: target } => {
+                // A ban is kept on the address of the connection, which bots don't have
+                let known = self.game.players.contains_key(&target)
+                    || self.game.spectators.contains_key(&target);
+                if self.game.is_host(&id) && known && !self.addresses.contains_key(&target) {
+                    let error = GameError::IllegalAction(
+                        "The address of the player is unknown, kick them instead".to_string(),
+                    );
+                    return vec![Event::new(Target::Player(id), error.into())];
+                }
+                Some(Action::Ban(target))
+            }
+            PacketType::Kicked { .. } => None, // Will only be sent to client
+            PacketType::TransferHost { id: target } => Some(Action::TransferHost(target)),
+            PacketType::HostChanged { .. } => None, // Will only be sent to client
+            PacketType::Snapshot { .. } => None,    // Will only be sent to client
+            PacketType::RoomClosed => None,         // Will only be sent to client
+            PacketType::CreateInvite => {
+                let packet = if self.game.is_host(&id) {
+                    PacketType::Invite {
+                        code: self.invite(),
+                    }
+                } else {
+                    GameError::NotHost.into()
+                };
+                return vec![Event::new(Target::Player(id), packet)];
+            }
+            PacketType::RevokeInvite { code } => {
+                let error = if !self.game.is_host(&id) {
+                    GameError::NotHost
+                } else if !self.invites.remove(&code) {
+                    GameError::InvalidInvite
+                } else {
+                    return Vec::new();
+                };
+                return vec![Event::new(Target::Player(id), error.into())];
+            }
+            PacketType::Invite { .. } => None, // Will only be sent to client
+            PacketType::JoinQueue { .. } => None, // Only sent to matchmaking
+            PacketType::LeaveQueue => None,    // Only sent to matchmaking
+            PacketType::Queued { .. } => None, // Will only be sent to client
+            PacketType::Matched { .. } => None, // Will only be sent to client
+            PacketType::Resync { .. } => None, // Handled above
+        };
+
+        match action {
+            Some(action) => self.game.handle(id, action),
+            None => vec![Event::new(
+                Target::Player(id),
+                GameError::IllegalAction(format!("{} can't be sent by players", name)).into(),
+            )],
+        }
+    }
+
+    // Dispatches the response to a packet. If the client sent a request id, it's attached to the
+    // errors sent back to the client, or acknowledged if there were none.
+    fn reply(&mut self, id: Uuid, request_id: Option<String>, mut events: Vec<Event>) {
+        if let Some(request_id) = request_id {
+            let mut failed = false;
+
+            for event in events.iter_mut().filter(|e| e.target == Target::Player(id)) {
+                if let PacketType::Error { request_id: r, .. } = &mut event.packet {
+                    *r = Some(request_id.clone());
+                    failed = true;
+                }
+            }
+
+            if !failed {
+                events.push(Event::new(
+                    Target::Player(id),
+                    PacketType::Ack { request_id },
+                ));
+            }
+        }
+
+        self.dispatch(events);
     }
 }
 
@@ -26,203 +638,293 @@ impl Actor for Lobby {
     type Context = Context<Self>;
 }
 
+impl Lobby {
+    // Schedules a timer for the current turn of the room if it has a deadline and none was scheduled yet
+    fn schedule_turn_timer(&mut self, room_id: Uuid, ctx: &mut Context<Self>) {
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            let turn = room.game.turn;
+
+            if let Some(deadline) = room.game.turn_deadline.filter(|_| room.timer_turn != turn) {
+                room.timer_turn = turn;
+
+                let delay = deadline
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+
+                ctx.run_later(delay, move |lobby, ctx| {
+                    if let Some(room) = lobby.rooms.get_mut(&room_id) {
+                        let events = room.game.timeout(turn);
+                        room.dispatch(events);
+                    }
+                    lobby.schedule_turn_timer(room_id, ctx);
+                });
+            }
+        }
+    }
+}
+
+impl Lobby {
+    // Gives up the seat of a player who didn't resume their session in time
+    fn expire_session(
+        &mut self,
+        room_id: Uuid,
+        id: Uuid,
+        disconnects: usize,
+        ctx: &mut Context<Self>,
+    ) {
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            // The player came back, possibly losing connection again since
+            if room
+                .game
+                .players
+                .get(&id)
+                .is_some_and(|p| p.is_connected || p.disconnects != disconnects)
+            {
+                return;
+            }
+
+            room.tokens.retain(|_, player| *player != id);
+            room.aliases.retain(|_, player| *player != id);
+            room.addresses.remove(&id);
+
+            let events = room.game.leave(id);
+            room.dispatch(events);
+
+            if room.sockets.is_empty() && !room.has_pending_sessions() {
+                self.rooms.remove(&room_id);
+            }
+        }
+
+        self.schedule_turn_timer(room_id, ctx);
+    }
+}
+
+impl Lobby {
+    // Removes a room created through the REST api or matchmaking if nobody connects to it in time
+    fn schedule_room_expiry(&self, room_id: Uuid, ctx: &mut Context<Self>) {
+        ctx.run_later(self.room_expiry, move |lobby, _| {
+            if lobby.rooms.get(&room_id).is_some_and(Room::is_unused) {
+                lobby.rooms.remove(&room_id);
+            }
+        });
+    }
+}
+
 impl Handler<Disconnect> for Lobby {
     type Result = ();
 
-    fn handle(&mut self, packet: Disconnect, _: &mut Context<Self>) {
+    fn handle(&mut self, packet: Disconnect, ctx: &mut Context<Self>) {
         if let Some(lobby) = self.rooms.get_mut(&packet.room_id) {
-            if lobby.game.players.len() > 1 {
-                let disconnected = lobby.game.players.get(&packet.id);
+            // Ignore connections that were replaced by a resumed session or already left
+            let id = lobby.resolve(&packet.id);
+            if !lobby.is_current(&packet.id) || !lobby.sockets.contains_key(&id) {
+                return;
+            }
 
-                if let Some(player) = disconnected {
-                    lobby.game.broadcast(&to_json(PacketType::Disconnect(
-                        packet.id,
-                        player.username.clone(),
-                    )));
+            // Keep the seat of a player in a running game so they can resume their session
+            if lobby.game.active && lobby.game.players.get(&id).is_some_and(|p| p.is_connected) {
+                lobby.sockets.remove(&id);
+                lobby.encodings.remove(&id);
+                lobby.greeted.remove(&id);
+                lobby.seqs.remove(&id);
+                lobby.history.remove(&id);
 
-                    lobby.game.leave(packet.id);
-                }
+                let events = lobby.game.disconnect(id);
+                lobby.dispatch(events);
+
+                let room_id = packet.room_id;
+                let disconnects = lobby.game.get_player(&id).disconnects;
+                ctx.run_later(self.grace_period, move |lobby, ctx| {
+                    lobby.expire_session(room_id, id, disconnects, ctx);
+                });
+            } else if lobby.sockets.len() > 1 || lobby.has_pending_sessions() {
+                // The room is kept for as long as a player can still resume their session
+                lobby.sockets.remove(&id);
+                lobby.encodings.remove(&id);
+                lobby.greeted.remove(&id);
+                lobby.addresses.remove(&id);
+                lobby.seqs.remove(&id);
+                lobby.history.remove(&id);
+                lobby.tokens.retain(|_, player| *player != id);
+                lobby.aliases.retain(|_, player| *player != id);
+
+                let events = lobby.game.leave(id);
+                lobby.dispatch(events);
             } else {
                 self.rooms.remove(&packet.room_id);
             }
         }
+
+        self.schedule_turn_timer(packet.room_id, ctx);
     }
 }
 
 impl Handler<Connect> for Lobby {
-    type Result = ();
+    type Result = Result<(), GameError>;
 
     fn handle(&mut self, packet: Connect, _: &mut Context<Self>) -> Self::Result {
-        // A very sexy one-liner
-        self.rooms.entry(packet.lobby_id).or_insert_with(Room::new);
-
-        if let Some(room) = self.rooms.get_mut(&packet.lobby_id) {
-            if room.game.active {
-                // TODO allow spectators. Currently they are sent an HTMLError when trying to join
-
-                let _ = &packet.addr.do_send(WsMessage(to_json(PacketType::Error(
-                    401,
-                    "Game you are trying to join has already started".to_string(),
-                ))));
-                return;
+        let room = match self.rooms.entry(packet.lobby_id) {
+            Entry::Occupied(room) => {
+                let room = room.into_mut();
+                room.admit(&packet.credentials, packet.ip)?;
+                room
+            }
+            Entry::Vacant(entry) => {
+                packet.options.check()?;
+                entry.insert(Room::new(packet.lobby_id, &packet.options))
             }
+        };
 
+        room.sockets.insert(packet.self_id, packet.addr);
+        if let Some(ip) = packet.ip {
+            room.addresses.insert(packet.self_id, ip);
+        }
+
+        // Late joiners spectate until they take a seat or resume a session
+        let events = if room.game.active {
+            room.game.spectate(packet.self_id)
+        } else {
             println!("Connection is waiting to join...");
+            room.game.join(packet.self_id)
+        };
+        room.dispatch(events);
 
-            room.game
-                .players
-                .insert(packet.self_id, Player::new(packet.self_id, &packet.addr));
+        Ok(())
+    }
+}
+
+impl Handler<CreateRoom> for Lobby {
+    type Result = Result<(RoomSettings, Uuid), GameError>;
 
-            room.game.emit(
-                &packet.self_id,
-                &to_json(PacketType::Message(
-                    "Server".to_string(),
-                    format!("{} is your own id", &packet.self_id),
-                )),
-            );
+    fn handle(&mut self, msg: CreateRoom, ctx: &mut Context<Self>) -> Self::Result {
+        msg.options.check()?;
+
+        let id = Uuid::new_v4();
+        let token = Uuid::new_v4();
+
+        let mut room = Room::new(id, &msg.options);
+        room.owner = Some(token);
+        let settings = room.settings();
+        self.rooms.insert(id, room);
+        self.schedule_room_expiry(id, ctx);
+
+        Ok((settings, token))
+    }
+}
+
+impl Handler<ListRooms> for Lobby {
+    type Result = MessageResult<ListRooms>;
+
+    fn handle(&mut self, msg: ListRooms, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.rooms
+                .values()
+                .filter(|room| room.public && (!msg.open || room.is_open()))
+                .map(|room| room.settings())
+                .collect(),
+        )
+    }
+}
+
+impl Handler<GetRoom> for Lobby {
+    type Result = Result<RoomSettings, GameError>;
+
+    fn handle(&mut self, msg: GetRoom, _: &mut Context<Self>) -> Self::Result {
+        self.rooms
+            .get(&msg.id)
+            .map(|room| room.settings())
+            .ok_or(GameError::UnknownRoom)
+    }
+}
+
+impl Handler<InviteToRoom> for Lobby {
+    type Result = Result<String, GameError>;
+
+    fn handle(&mut self, msg: InviteToRoom, _: &mut Context<Self>) -> Self::Result {
+        let room = self.rooms.get_mut(&msg.id).ok_or(GameError::UnknownRoom)?;
+        if room.owner != Some(msg.token) {
+            return Err(GameError::NotOwner);
         }
+
+        Ok(room.invite())
+    }
+}
+
+impl Handler<DeleteRoom> for Lobby {
+    type Result = Result<(), GameError>;
+
+    fn handle(&mut self, msg: DeleteRoom, _: &mut Context<Self>) -> Self::Result {
+        let room = self.rooms.get_mut(&msg.id).ok_or(GameError::UnknownRoom)?;
+        if room.owner != Some(msg.token) {
+            return Err(GameError::NotOwner);
+        }
+
+        room.dispatch(vec![Event::new(Target::All, PacketType::RoomClosed)]);
+        for socket in room.sockets.values() {
+            socket.do_send(WsMessage::Close);
+        }
+        self.rooms.remove(&msg.id);
+
+        Ok(())
     }
 }
 
 impl Handler<Packet> for Lobby {
     type Result = ();
 
-    fn handle(&mut self, packet: Packet, _ctx: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, packet: Packet, ctx: &mut Context<Self>) -> Self::Result {
         if let Some(room) = self.rooms.get_mut(&packet.room_id) {
-            // Ignore all the request sent by non-players
-            if !room.game.players.contains_key(&packet.id) {
+            let id = room.resolve(&packet.id);
+
+            let data = serde_json::from_str(&packet.data);
+
+            // Clients that don't start with a 'Hello' are assumed to speak version 1
+            if !room.greeted.contains(&id) && !matches!(data, Ok(PacketType::Hello { .. })) {
+                room.reply(
+                    id,
+                    packet.request_id(),
+                    vec![Event::new(
+                        Target::Player(id),
+                        GameError::IncompatibleVersion(1).into(),
+                    )],
+                );
+                room.close(id);
                 return;
             }
-            let data: Result<PacketType> = serde_json::from_str(&packet.data);
-
-            if let Ok(packet_data) = data {
-                match packet_data {
-                    PacketType::Register(username) => {
-                        if room.game.get_player(&packet.id).is_connected {
-                            room.game.emit(
-                                &packet.id,
-                                &to_json(PacketType::Error(
-                                    401,
-                                    "Instance already exists".to_string(),
-                                )),
-                            );
-                            return;
-                        }
-                        // Initialize the player
-                        room.game.init_player(&packet.id, &username);
-
-                        // Broadcast the join-event
-                        room.game.broadcast_ignore_self(
-                            packet.id,
-                            &to_json(PacketType::Connect(packet.id, username.clone())),
-                        );
-
-                        // Emit the current game-data to the player
-                        room.game.emit(
-                            &packet.id,
-                            &to_json(PacketType::GameData(
-                                packet.id,
-                                username,
-                                room.game.players.map_username(),
-                            )),
-                        )
-                    }
-                    PacketType::GameData(_, _, _) => {} // Will only be sent to client
-                    PacketType::Connect(_, _) => {}     // Will only be sent to client
-                    PacketType::Disconnect(_, _) => {}  // Will only be sent to client
-                    PacketType::Message(sender, content) => {
-                        room.game
-                            .broadcast(&to_json(PacketType::Message(sender, content)));
-                    }
-                    PacketType::StartGame(_options) => {
-                        let host: bool = room.game.get_player(&packet.id).is_host;
-
-                        if !host {
-                            room.game.emit(
-                                &packet.id,
-                                &to_json(PacketType::Error(
-                                    401,
-                                    "You cannot start the game".to_string(),
-                                )),
-                            );
-                            return;
-                        }
-
-                        if room.game.players.len() < 2 {
-                            room.game.emit(
-                                &packet.id,
-                                &to_json(PacketType::Error(
-                                    401,
-                                    "Cannot start the game alone".to_string(),
-                                )),
-                            );
-                            return;
-                        }
-
-                        self.rooms.get_mut(&packet.room_id).unwrap().game.start();
-                    }
-                    PacketType::StatusUpdatePublic(_, _, _, _) => {} // Will only be sent to client
-                    PacketType::StatusUpdatePrivate(_, _) => {}      // Will only be sent to client
-                    PacketType::AllowedCardsUpdate(_) => {}          // Will only be sent to client
-                    PacketType::DrawCard(amount) => {
-                        if room.game.current_turn.unwrap_or_default() != packet.id {
-                            room.game.emit(
-                                &packet.id,
-                                &to_json(PacketType::Error(401, "It's not your turn".to_string())),
-                            );
-                            return;
-                        }
-
-                        room.game.draw_cards(amount.into(), packet.id);
-                        room.game.update_card_status(&packet.id);
-                        room.game.update_allowed_status(&packet.id);
-                    }
-                    PacketType::PlaceCard(index) => {
-                        if room.game.current_turn.unwrap_or_default() != packet.id {
-                            room.game.emit(
-                                &packet.id,
-                                &to_json(PacketType::Error(401, "It's not your turn".to_string())),
-                            );
-                            return;
-                        }
-
-                        room.game.place_card(index, packet.id);
-                        room.game.update_card_status(&packet.id);
-                        room.game.update_allowed_status(&packet.id);
-                    }
-                    PacketType::EndTurn => {
-                        if room.game.current_turn.unwrap_or_default() != packet.id {
-                            room.game.emit(
-                                &packet.id,
-                                &to_json(PacketType::Error(401, "It's not your turn".to_string())),
-                            );
-                            return;
-                        }
-
-                        room.game.end_turn(packet.id);
-                    }
-                    PacketType::ColorSwitch(color) => {
-                        if room.game.current_turn.unwrap_or_default() != packet.id {
-                            room.game.emit(
-                                &packet.id,
-                                &to_json(PacketType::Error(401, "It's not your turn".to_string())),
-                            );
-                            return;
-                        }
-
-                        room.game.switch_color(color);
-                        room.game.update_card_status(&packet.id);
-                        room.game.update_allowed_status(&packet.id);
-                    }
-                    PacketType::TurnUpdate(_, _) => {} // Will only be sent to client
-                    PacketType::Error(_, _) => {}
-                    PacketType::WinUpdate(_, _, _, _) => {} // Will only be sent to client
+
+            let data: PacketType = match data {
+                Ok(data) => data,
+                Err(e) => {
+                    room.reply(
+                        id,
+                        packet.request_id(),
+                        vec![Event::new(
+                            Target::Player(id),
+                            GameError::MalformedPacket(e.to_string()).into(),
+                        )],
+                    );
+                    return;
                 }
+            };
+
+            let incompatible =
+                matches!(data, PacketType::Hello { version, .. } if !is_supported(version));
+            let events = room.process(id, data);
+
+            // A resumed session answers on the socket of the player
+            let id = room.resolve(&packet.id);
+            room.reply(id, packet.request_id(), events);
+
+            // Incompatible clients are disconnected once they have been told why
+            if incompatible {
+                room.close(id);
             }
         } else {
             println!("{:?}", self.rooms);
         }
 
+        self.schedule_turn_timer(packet.room_id, ctx);
+
         println!(
             "DEBUG: [{}] {} > {:?} ",
             packet.room_id, packet.id, packet.json
@@ -230,6 +932,76 @@ impl Handler<Packet> for Lobby {
     }
 }
 
-pub fn to_json(data: PacketType) -> String {
-    serde_json::to_string(&data).unwrap()
+// Sends a packet to a connection that isn't part of a room
+fn send_direct(addr: &Socket, packet: PacketType) {
+    addr.do_send(Encoding::Json.encode(&Envelope {
+        seq: 0,
+        packet: &packet,
+    }));
+}
+
+impl Handler<Enqueue> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: Enqueue, ctx: &mut Context<Self>) -> Self::Result {
+        let options = RoomOptions {
+            max_players: Some(msg.players),
+            options: msg.options,
+            ..RoomOptions::default()
+        };
+        if let Err(e) = options.check() {
+            send_direct(&msg.addr, e.into());
+            return;
+        }
+
+        // The matched room has to be able to deal a hand to every player
+        if let Err(e) = options.options.validate(msg.players, Card::deck_size()) {
+            send_direct(&msg.addr, GameError::from(e).into());
+            return;
+        }
+
+        self.queue.retain(|ticket| ticket.id != msg.id);
+        self.queue.push(Ticket {
+            id: msg.id,
+            addr: msg.addr,
+            players: msg.players,
+            options: options.options.clone(),
+        });
+
+        let (matched, waiting): (Vec<Ticket>, Vec<Ticket>) = self
+            .queue
+            .drain(..)
+            .partition(|ticket| ticket.players == msg.players && ticket.options == options.options);
+        self.queue = waiting;
+
+        // Not enough players yet, put them back and let them know how many are waiting
+        if matched.len() < msg.players {
+            for ticket in &matched {
+                send_direct(
+                    &ticket.addr,
+                    PacketType::Queued {
+                        waiting: matched.len(),
+                    },
+                );
+            }
+            self.queue.extend(matched);
+            return;
+        }
+
+        let id = Uuid::new_v4();
+        self.rooms.insert(id, Room::new(id, &options));
+        self.schedule_room_expiry(id, ctx);
+
+        for ticket in matched {
+            send_direct(&ticket.addr, PacketType::Matched { room: id });
+        }
+    }
+}
+
+impl Handler<Dequeue> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: Dequeue, _: &mut Context<Self>) -> Self::Result {
+        self.queue.retain(|ticket| ticket.id != msg.id);
+    }
 }
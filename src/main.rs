@@ -1,19 +1,14 @@
-mod errors;
-mod lobby;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-mod packets;
-mod ws;
-use lobby::Lobby;
-mod game;
-mod messages;
-mod start_connection;
 use actix::Actor;
-use actix_web::{App, HttpServer};
+use actix_web::{web::Data, App, HttpServer};
 use clap::{Arg, Command};
 use colored::Colorize;
 use serde_derive::Deserialize;
-use start_connection::start_connection as start_connection_route;
 use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use uno_server::lobby::Lobby;
+use uno_server::matchmaking::matchmaking;
+use uno_server::rooms::{create_invite, create_room, delete_room, get_room, list_rooms};
+use uno_server::start_connection::start_connection as start_connection_route;
 
 #[derive(Deserialize)]
 struct Config {
@@ -62,8 +57,15 @@ async fn main() -> std::io::Result<()> {
 
     HttpServer::new(move || {
         App::new()
+            // The room api and matchmaking have to be registered before '/{group_id}'
+            .service(create_room)
+            .service(list_rooms)
+            .service(get_room)
+            .service(delete_room)
+            .service(create_invite)
+            .service(matchmaking)
             .service(start_connection_route)
-            .data(chat_server.clone())
+            .app_data(Data::new(chat_server.clone()))
     })
     .bind(config.listen_addr)?
     .run()
@@ -0,0 +1,130 @@
+use crate::errors::GameError;
+use crate::lobby::Lobby;
+use crate::messages::{Dequeue, Enqueue, WsMessage};
+use crate::packets::{Envelope, PacketType};
+use crate::ws::{CLIENT_TIMEOUT, HEARTBEAT_INTERVAL};
+use actix::{Actor, ActorContext, Addr, AsyncContext, Handler, Running, StreamHandler};
+use actix_web::{get, web::Data, web::Payload, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use std::time::Instant;
+use uuid::Uuid;
+
+// Connection waiting in the matchmaking queue. Once matched, the client closes it and connects
+// to the room it was sent.
+pub struct QueueConn {
+    id: Uuid,
+    lobby_addr: Addr<Lobby>,
+    hb: Instant,
+}
+
+impl QueueConn {
+    pub fn new(lobby: Addr<Lobby>) -> QueueConn {
+        QueueConn {
+            id: Uuid::new_v4(),
+            lobby_addr: lobby,
+            hb: Instant::now(),
+        }
+    }
+
+    fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+                ctx.stop();
+                return;
+            }
+
+            ctx.ping(b"hi");
+        });
+    }
+
+    fn error(&self, ctx: &mut ws::WebsocketContext<Self>, error: GameError) {
+        let packet = PacketType::from(error);
+        ctx.text(
+            serde_json::to_string(&Envelope {
+                seq: 0,
+                packet: &packet,
+            })
+            .unwrap(),
+        );
+    }
+}
+
+impl Actor for QueueConn {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hb(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.lobby_addr.do_send(Dequeue { id: self.id });
+        Running::Stop
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for QueueConn {
+    fn handle(&mut self, packet: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match packet {
+            Ok(ws::Message::Ping(packet)) => {
+                self.hb = Instant::now();
+                ctx.pong(&packet);
+            }
+            Ok(ws::Message::Pong(_)) => {
+                self.hb = Instant::now();
+            }
+            Ok(ws::Message::Text(text)) => match serde_json::from_str(&text) {
+                Ok(PacketType::JoinQueue { players, options }) => {
+                    self.lobby_addr.do_send(Enqueue {
+                        id: self.id,
+                        addr: ctx.address().recipient(),
+                        players,
+                        options: options.unwrap_or_default(),
+                    })
+                }
+                Ok(PacketType::LeaveQueue) => self.lobby_addr.do_send(Dequeue { id: self.id }),
+                Ok(_) => self.error(
+                    ctx,
+                    GameError::IllegalAction(
+                        "Only 'JoinQueue' and 'LeaveQueue' can be sent to matchmaking".to_string(),
+                    ),
+                ),
+                Err(e) => self.error(ctx, GameError::MalformedPacket(e.to_string())),
+            },
+            Ok(ws::Message::Binary(_)) => self.error(
+                ctx,
+                GameError::MalformedPacket("Matchmaking only supports JSON".to_string()),
+            ),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(ws::Message::Continuation(_)) => ctx.stop(),
+            Ok(ws::Message::Nop) => (),
+            Err(_) => ctx.stop(),
+        }
+    }
+}
+
+impl Handler<WsMessage> for QueueConn {
+    type Result = ();
+
+    fn handle(&mut self, msg: WsMessage, ctx: &mut Self::Context) {
+        match msg {
+            WsMessage::Text(text) => ctx.text(text),
+            WsMessage::Binary(bin) => ctx.binary(bin),
+            WsMessage::Close => {
+                ctx.close(None);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+#[get("/matchmaking")]
+pub async fn matchmaking(
+    req: HttpRequest,
+    stream: Payload,
+    srv: Data<Addr<Lobby>>,
+) -> Result<HttpResponse, Error> {
+    ws::start(QueueConn::new(srv.get_ref().clone()), &req, stream)
+}
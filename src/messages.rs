@@ -1,19 +1,56 @@
-use crate::errors::HTMLError;
+use crate::errors::GameError;
+use crate::lobby::{Credentials, RoomOptions};
+use crate::options::GameOptions;
+use crate::packets::{Envelope, RoomSettings};
 use actix::prelude::{Message, Recipient};
 use serde::{Deserialize, Serialize};
-use serde_json::{Result, Value};
+use serde_json::Value;
+use std::net::IpAddr;
 use uuid::Uuid;
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct WsMessage(pub String);
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+    Close,
+}
+
+// Wire format of a connection. Clients ask for MessagePack with the "msgpack" capability in 'Hello'.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    // Sent over binary frames, structs are encoded as maps like in JSON
+    MessagePack,
+}
+
+impl Encoding {
+    pub fn encode(self, envelope: &Envelope) -> WsMessage {
+        match self {
+            Encoding::Json => WsMessage::Text(serde_json::to_string(envelope).unwrap()),
+            Encoding::MessagePack => {
+                let mut buf = Vec::new();
+                let mut serializer = rmp_serde::Serializer::new(&mut buf)
+                    .with_struct_map()
+                    .with_human_readable();
+                envelope.serialize(&mut serializer).unwrap();
+                WsMessage::Binary(buf)
+            }
+        }
+    }
+}
 
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), GameError>")]
 pub struct Connect {
     pub addr: Recipient<WsMessage>,
     pub lobby_id: Uuid,
     pub self_id: Uuid,
+    pub options: RoomOptions,
+    pub credentials: Credentials,
+    // Address of the client, checked against the bans of the room
+    pub ip: Option<IpAddr>,
 }
 
 #[derive(Message)]
@@ -23,6 +60,58 @@ pub struct Disconnect {
     pub id: Uuid,
 }
 
+// Creates a room and returns it together with the token needed to delete it
+#[derive(Message)]
+#[rtype(result = "Result<(RoomSettings, Uuid), GameError>")]
+pub struct CreateRoom {
+    pub options: RoomOptions,
+}
+
+// Returns the public rooms, or only the ones that can be joined right now when 'open' is set
+#[derive(Message)]
+#[rtype(result = "Vec<RoomSettings>")]
+pub struct ListRooms {
+    pub open: bool,
+}
+
+// Puts a matchmaking connection in the queue, replacing its previous preferences
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Enqueue {
+    pub id: Uuid,
+    pub addr: Recipient<WsMessage>,
+    pub players: usize,
+    pub options: GameOptions,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Dequeue {
+    pub id: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<RoomSettings, GameError>")]
+pub struct GetRoom {
+    pub id: Uuid,
+}
+
+// Creates an invite to the room and returns its code
+#[derive(Message)]
+#[rtype(result = "Result<String, GameError>")]
+pub struct InviteToRoom {
+    pub id: Uuid,
+    pub token: Uuid,
+}
+
+// Closes the connections of the room and removes it
+#[derive(Message)]
+#[rtype(result = "Result<(), GameError>")]
+pub struct DeleteRoom {
+    pub id: Uuid,
+    pub token: Uuid,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 #[derive(Serialize, Deserialize)]
@@ -35,14 +124,18 @@ pub struct Packet {
 
 impl Packet {
     pub fn try_parse(data: &str) -> Value {
-        let v: Result<Value> = serde_json::from_str(data);
+        let v: serde_json::Result<Value> = serde_json::from_str(data);
 
-        match v {
-            Ok(v) => v,
-            Err(e) => {
-                serde_json::from_str(&HTMLError::to_json(HTMLError::new(400, &e.to_string())))
-                    .unwrap()
-            }
+        // Malformed packets are kept as they are for logging
+        v.unwrap_or_else(|_| Value::String(data.to_string()))
+    }
+
+    // Returns the request id the client sent along with the packet
+    pub fn request_id(&self) -> Option<String> {
+        match self.json.get("request_id")? {
+            Value::String(id) => Some(id.clone()),
+            Value::Number(id) => Some(id.to_string()),
+            _ => None,
         }
     }
 
@@ -54,4 +147,22 @@ impl Packet {
             json: Packet::try_parse(data),
         }
     }
+
+    // Converts a MessagePack encoded packet to JSON, so both formats are handled the same way
+    pub fn from_binary(id: Uuid, data: &[u8], room_id: Uuid) -> Packet {
+        match rmp_serde::from_slice::<Value>(data) {
+            Ok(json) => Packet {
+                id,
+                data: json.to_string(),
+                room_id,
+                json,
+            },
+            Err(e) => Packet {
+                id,
+                data: format!("Invalid MessagePack: {}", e),
+                room_id,
+                json: Value::Null,
+            },
+        }
+    }
 }
@@ -0,0 +1,106 @@
+use crate::errors::OptionsError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Rule deciding when a player who hasn't placed a card can end their turn
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub enum DrawRule {
+    // The turn can be ended after drawing three times
+    DrawThree,
+    // The turn can be ended once the last drawn card is playable
+    UntilPlayable,
+}
+
+// House rules of a game, sent by the host with the 'StartGame' packet
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(default)]
+pub struct GameOptions {
+    pub hand_size: usize,
+    // Draw-cards can be placed on top of each other to pass the penalty forward
+    pub draw_stacking: bool,
+    // The same player can place multiple block-cards to skip more players
+    pub block_stacking: bool,
+    // Drawing is not allowed while the player has a playable card
+    pub forced_play: bool,
+    pub draw_rule: DrawRule,
+    // Players can place an identical card out of turn, continuing the game from them
+    pub jump_in: bool,
+    // Placing a seven swaps hands with a chosen player, placing a zero rotates all hands
+    pub seven_zero: bool,
+    // Amount of cards drawn when caught without calling uno
+    pub uno_penalty: usize,
+    // Play multiple rounds until a player reaches the score, a single round is played when not set
+    pub target_score: Option<usize>,
+    // Seconds a player has to end their turn before the server ends it for them
+    pub turn_timeout: Option<u64>,
+    // Consecutive timed out turns after which the player is removed from the game
+    pub max_timeouts: usize,
+}
+
+impl GameOptions {
+    pub const MAX_HAND_SIZE: usize = 20;
+    // Longest turn timeout in seconds, a day
+    pub const MAX_TURN_TIMEOUT: u64 = 24 * 60 * 60;
+
+    // Parses the options sent with the 'StartGame' packet. An empty string or "None" results in the default options.
+    pub fn parse(data: &str) -> Result<GameOptions, OptionsError> {
+        if data.trim().is_empty() || data == "None" {
+            return Ok(GameOptions::default());
+        }
+
+        let options: GameOptions =
+            serde_json::from_str(data).map_err(|e| OptionsError::Malformed(e.to_string()))?;
+        options.check()?;
+
+        Ok(options)
+    }
+
+    // Checks that the values of the options are within their limits
+    pub fn check(&self) -> Result<(), OptionsError> {
+        if self.hand_size == 0 || self.hand_size > GameOptions::MAX_HAND_SIZE {
+            return Err(OptionsError::HandSize(self.hand_size));
+        }
+
+        if self.target_score == Some(0) {
+            return Err(OptionsError::TargetScore);
+        }
+
+        if self
+            .turn_timeout
+            .is_some_and(|secs| secs == 0 || secs > GameOptions::MAX_TURN_TIMEOUT)
+            || self.max_timeouts == 0
+        {
+            return Err(OptionsError::TurnTimeout);
+        }
+
+        Ok(())
+    }
+
+    // Checks that the options can be used in a game with the given amount of players and cards
+    pub fn validate(&self, player_count: usize, deck_size: usize) -> Result<(), OptionsError> {
+        // One card is always needed for the start card
+        if player_count * self.hand_size >= deck_size {
+            return Err(OptionsError::NotEnoughCards(player_count, self.hand_size));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for GameOptions {
+    fn default() -> Self {
+        GameOptions {
+            hand_size: 8,
+            draw_stacking: true,
+            block_stacking: true,
+            forced_play: false,
+            draw_rule: DrawRule::DrawThree,
+            jump_in: false,
+            seven_zero: false,
+            uno_penalty: 2,
+            target_score: None,
+            turn_timeout: None,
+            max_timeouts: 4,
+        }
+    }
+}
@@ -1,25 +1,316 @@
+use crate::bot::BotStrategy;
 use crate::game::{Card, Color, GameStatistics};
+use crate::options::GameOptions;
+use schemars::gen::SchemaSettings;
+use schemars::schema::RootSchema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
+use std::time::SystemTime;
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug, strum_macros::Display)]
+// Compatibility policy of the protocol:
+// - Packets and fields can be added at any time without changing the version. Clients must ignore
+//   packet types and fields they don't know, and new inbound fields are optional.
+// - Removing or renaming a packet or a field, or changing the type of a field, bumps 'PROTOCOL_VERSION'.
+// - Clients announce their version with 'Hello' and are rejected if it's older than 'MIN_PROTOCOL_VERSION'
+//   or newer than the server's version. 'Hello' must be the first packet of a connection, clients
+//   that send anything else first are treated as version 1.
+//
+// Version 1 used positional payloads, version 2 uses named fields.
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+pub fn is_supported(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+// Optional features supported by the server, announced in the 'Welcome' packet
+pub const FEATURES: [&str; 7] = [
+    "resume",
+    "spectators",
+    "bots",
+    "house-rules",
+    "turn-timer",
+    "match",
+    "msgpack",
+];
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct PlayerEntry {
+    pub id: Uuid,
+    pub username: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ScoreEntry {
+    pub id: Uuid,
+    pub username: String,
+    pub score: usize,
+}
+
+// Hand size of another player, as seen in a 'Snapshot'
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct OpponentEntry {
+    pub id: Uuid,
+    pub username: String,
+    pub cards: usize,
+}
+
+// Settings of the room sent to the client in response to 'Hello' and by the REST api
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct RoomSettings {
+    pub id: Uuid,
+    pub active: bool,
+    pub players: usize,
+    pub spectators: usize,
+    pub max_players: usize,
+    // Listed by 'GET /rooms'
+    pub public: bool,
+    // A password or an invite is needed to join
+    pub locked: bool,
+    pub options: GameOptions,
+}
+
+// Every inbound packet can carry an optional 'request_id' next to 'type' and 'data', which is echoed
+// back on the 'Ack' or 'Error' it results in.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, strum_macros::Display)]
 #[serde(tag = "type", content = "data")]
 pub enum PacketType {
-    Register(String),                                          // username
-    GameData(Uuid, String, Vec<(Uuid, String)>), // self_id, self_username, Vec<(id, username)>
-    Connect(Uuid, String),                       // id, username
-    Disconnect(Uuid, String),                    // id, username
-    Message(String, String),                     // content
-    StartGame(String),                           // option
-    StatusUpdatePublic(Uuid, String, usize, Card), // id, username, card-count, current
-    StatusUpdatePrivate(Vec<Card>, Card),        // cards, current
-    AllowedCardsUpdate(Vec<Card>),               // allowed-cards
-    DrawCard(u8),                                // amount
-    PlaceCard(usize),                            // index
-    EndTurn,                                     //
-    ColorSwitch(Color),                          // color
-    TurnUpdate(Uuid, Uuid),                      // current, next
-    WinUpdate(Uuid, String, VecDeque<String>, GameStatistics), // id, username, placements, statistics
-    Error(u64, String),                                        // error-code, body
+    Hello {
+        version: u32,
+        // Optional features the client wants to use, "msgpack" switches the connection to MessagePack
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
+    Welcome {
+        version: u32,
+        features: Vec<String>,
+        room: RoomSettings,
+    },
+    Register {
+        username: String,
+    },
+    GameData {
+        id: Uuid,
+        username: String,
+        players: Vec<PlayerEntry>,
+    },
+    Connect {
+        id: Uuid,
+        username: String,
+    },
+    Disconnect {
+        id: Uuid,
+        username: String,
+    },
+    Message {
+        sender: String,
+        content: String,
+    },
+    StartGame {
+        // House rules, the default ones are used when not set
+        #[serde(default)]
+        options: Option<GameOptions>,
+    },
+    StatusUpdatePublic {
+        id: Uuid,
+        username: String,
+        cards: usize,
+        top: Card,
+    },
+    StatusUpdatePrivate {
+        cards: Vec<Card>,
+        top: Card,
+    },
+    AllowedCardsUpdate {
+        cards: Vec<Card>,
+    },
+    DrawCard {
+        amount: u8,
+    },
+    PlaceCard {
+        index: usize,
+    },
+    EndTurn,
+    ColorSwitch {
+        color: Color,
+    },
+    TurnUpdate {
+        current: Uuid,
+        next: Uuid,
+        deadline: Option<SystemTime>,
+    },
+    WinUpdate {
+        id: Uuid,
+        username: String,
+        placements: VecDeque<String>,
+        statistics: GameStatistics,
+    },
+    Error {
+        code: String,
+        message: String,
+        // Request id of the packet that caused the error, if the client sent one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    // Confirms that a packet sent with a request id was handled without errors
+    Ack {
+        request_id: String,
+    },
+    DeckReshuffled {
+        deck_size: usize,
+    },
+    Deadlock,
+    SwapHands {
+        target: Uuid,
+    },
+    CallUno,
+    CatchUno {
+        target: Uuid,
+    },
+    UnoCalled {
+        id: Uuid,
+    },
+    UnoCaught {
+        catcher: Uuid,
+        target: Uuid,
+        penalty: usize,
+    },
+    ChallengeDrawFour,
+    ChallengeResult {
+        challenger: Uuid,
+        offender: Uuid,
+        successful: bool,
+    },
+    ChallengeReveal {
+        offender: Uuid,
+        cards: Vec<Card>,
+    },
+    MatchUpdate {
+        round: usize,
+        scores: Vec<ScoreEntry>,
+        winner: Option<Uuid>,
+    },
+    PlayerAfk {
+        id: Uuid,
+        afk: bool,
+    },
+    Session {
+        token: Uuid,
+    },
+    Resume {
+        token: Uuid,
+    },
+    ConnectionUpdate {
+        id: Uuid,
+        connected: bool,
+    },
+    SpectatorMessage {
+        sender: String,
+        content: String,
+    },
+    TakeSeat,
+    AddBot {
+        strategy: BotStrategy,
+    },
+    RemoveBot {
+        id: Uuid,
+    },
+    // Everything the recipient can see of a running game
+    Snapshot {
+        // Starting from the next player
+        turn_order: Vec<Uuid>,
+        current: Uuid,
+        next: Uuid,
+        reversed: bool,
+        draw_stack: usize,
+        block_stack: usize,
+        top: Card,
+        color: Color,
+        deadline: Option<SystemTime>,
+        opponents: Vec<OpponentEntry>,
+        // Own hand and the cards of it that can be placed, empty for spectators
+        hand: Vec<Card>,
+        allowed: Vec<Card>,
+        deck_size: usize,
+    },
+    // The room was deleted, the connection is closed after this
+    RoomClosed,
+    // Creates a single-use invite, only the host can manage the invites
+    CreateInvite,
+    RevokeInvite {
+        code: String,
+    },
+    // Invite created by the host, used as '/{room}?invite={code}'
+    Invite {
+        code: String,
+    },
+    // Sent to '/matchmaking' to wait for other players with the same preferences
+    JoinQueue {
+        players: usize,
+        // House rules, the default ones are used when not set
+        #[serde(default)]
+        options: Option<GameOptions>,
+    },
+    LeaveQueue,
+    // Amount of players waiting with the same preferences, the client included
+    Queued {
+        waiting: usize,
+    },
+    // A room was created for the matched players, who connect to it with '/{room}'
+    Matched {
+        room: Uuid,
+    },
+    // Removes a player or a spectator from the room, only the host can do this
+    Kick {
+        id: Uuid,
+    },
+    // Kicks the player and keeps their address and resume-token from being used to come back
+    Ban {
+        id: Uuid,
+    },
+    // Sent to everyone before the player is removed, the connection is closed after this
+    Kicked {
+        id: Uuid,
+        banned: bool,
+    },
+    TransferHost {
+        id: Uuid,
+    },
+    // The host was passed to another player, either by the host or because the host left
+    HostChanged {
+        id: Uuid,
+    },
+    // Asks the server to send the events starting from the given sequence number again
+    Resync {
+        from_seq: u64,
+    },
+}
+
+// Outbound packet stamped with the sequence number of the connection, sent as '{"seq": 1, "type": .., "data": ..}'.
+// Every connection counts up from 1 without gaps, packets sent outside of a room use 0.
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct Envelope<'a> {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub packet: &'a PacketType,
+}
+
+// Inbound packet as sent by clients, sent as '{"type": .., "data": .., "request_id": ..}'
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct Request {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub packet: PacketType,
+}
+
+// JSON schema of every packet, with 'Request' and 'Envelope' describing the inbound and outbound
+// messages. Printed by 'uno-schema' and kept in 'schema/protocol.json' for the clients.
+pub fn schema() -> RootSchema {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    gen.subschema_for::<Request>();
+    gen.subschema_for::<Envelope>();
+    gen.into_root_schema_for::<PacketType>()
 }
@@ -0,0 +1,103 @@
+use crate::lobby::{Lobby, RoomOptions};
+use crate::messages::{CreateRoom, DeleteRoom, GetRoom, InviteToRoom, ListRooms};
+use crate::packets::RoomSettings;
+use actix::Addr;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{
+    delete, get, post, web::Data, web::Json, web::Path, web::Query, Error, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize)]
+struct CreatedRoom {
+    #[serde(flatten)]
+    room: RoomSettings,
+    // Needed to delete the room
+    token: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct Owner {
+    token: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct Filter {
+    // Only list the rooms that can be joined right now
+    #[serde(default)]
+    open: bool,
+}
+
+#[post("/rooms")]
+pub async fn create_room(
+    options: Json<RoomOptions>,
+    srv: Data<Addr<Lobby>>,
+) -> Result<HttpResponse, Error> {
+    let (room, token) = srv
+        .send(CreateRoom {
+            options: options.into_inner(),
+        })
+        .await
+        .map_err(ErrorInternalServerError)??;
+
+    Ok(HttpResponse::Created().json(CreatedRoom { room, token }))
+}
+
+#[get("/rooms")]
+pub async fn list_rooms(
+    filter: Query<Filter>,
+    srv: Data<Addr<Lobby>>,
+) -> Result<HttpResponse, Error> {
+    let rooms = srv
+        .send(ListRooms { open: filter.open })
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(rooms))
+}
+
+#[get("/rooms/{id}")]
+pub async fn get_room(path: Path<Uuid>, srv: Data<Addr<Lobby>>) -> Result<HttpResponse, Error> {
+    let room = srv
+        .send(GetRoom {
+            id: path.into_inner(),
+        })
+        .await
+        .map_err(ErrorInternalServerError)??;
+
+    Ok(HttpResponse::Ok().json(room))
+}
+
+#[post("/rooms/{id}/invites")]
+pub async fn create_invite(
+    path: Path<Uuid>,
+    owner: Query<Owner>,
+    srv: Data<Addr<Lobby>>,
+) -> Result<HttpResponse, Error> {
+    let code = srv
+        .send(InviteToRoom {
+            id: path.into_inner(),
+            token: owner.token,
+        })
+        .await
+        .map_err(ErrorInternalServerError)??;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({ "code": code })))
+}
+
+#[delete("/rooms/{id}")]
+pub async fn delete_room(
+    path: Path<Uuid>,
+    owner: Query<Owner>,
+    srv: Data<Addr<Lobby>>,
+) -> Result<HttpResponse, Error> {
+    srv.send(DeleteRoom {
+        id: path.into_inner(),
+        token: owner.token,
+    })
+    .await
+    .map_err(ErrorInternalServerError)??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,8 +1,11 @@
-use crate::lobby::Lobby;
+use crate::lobby::{Credentials, Lobby, RoomOptions};
 use crate::ws::WsConn;
 use actix::Addr;
-use actix_web::{get, web::Data, web::Path, web::Payload, Error, HttpRequest, HttpResponse};
+use actix_web::{
+    get, web::Data, web::Path, web::Payload, web::Query, Error, HttpRequest, HttpResponse,
+};
 use actix_web_actors::ws;
+use std::net::{IpAddr, SocketAddr};
 use uuid::Uuid;
 
 #[get("/{group_id}")]
@@ -10,11 +13,29 @@ pub async fn start_connection(
     req: HttpRequest,
     stream: Payload,
     path: Path<Uuid>,
+    options: Query<RoomOptions>,
+    credentials: Query<Credentials>,
     srv: Data<Addr<Lobby>>,
 ) -> Result<HttpResponse, Error> {
     let group_id = path.into_inner();
-    let ws = WsConn::new(group_id, srv.get_ref().clone());
+    let ws = WsConn::new(
+        group_id,
+        srv.get_ref().clone(),
+        options.into_inner(),
+        credentials.into_inner(),
+        client_ip(&req),
+    );
 
     let resp = ws::start(ws, &req, stream)?;
     Ok(resp)
 }
+
+// Address of the client, as reported by the reverse proxy when the server runs behind one
+fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let info = req.connection_info();
+    let addr = info.realip_remote_addr()?;
+    addr.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| addr.trim_matches(|c| c == '[' || c == ']').parse())
+        .ok()
+}
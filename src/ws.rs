@@ -1,31 +1,45 @@
-use crate::lobby::Lobby;
+use crate::lobby::{Credentials, Lobby, RoomOptions};
 use crate::messages::{Connect, Disconnect, Packet, WsMessage};
+use crate::packets::{Envelope, PacketType};
 use actix::ActorFutureExt;
 use actix::{fut, ActorContext, ContextFutureSpawner, WrapFuture};
 use actix::{Actor, Addr, Running, StreamHandler};
 use actix::{AsyncContext, Handler};
 use actix_web_actors::ws;
 use actix_web_actors::ws::Message::Text;
+use std::net::IpAddr;
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct WsConn {
     room: Uuid,
     lobby_addr: Addr<Lobby>,
     hb: Instant,
     id: Uuid,
+    options: RoomOptions,
+    credentials: Credentials,
+    ip: Option<IpAddr>,
 }
 
 impl WsConn {
-    pub fn new(room: Uuid, lobby: Addr<Lobby>) -> WsConn {
+    pub fn new(
+        room: Uuid,
+        lobby: Addr<Lobby>,
+        options: RoomOptions,
+        credentials: Credentials,
+        ip: Option<IpAddr>,
+    ) -> WsConn {
         WsConn {
             id: Uuid::new_v4(),
             room,
             hb: Instant::now(),
             lobby_addr: lobby,
+            options,
+            credentials,
+            ip,
         }
     }
 }
@@ -42,11 +56,27 @@ impl Actor for WsConn {
                 addr: addr.recipient(),
                 lobby_id: self.room,
                 self_id: self.id,
+                options: self.options.clone(),
+                credentials: self.credentials.clone(),
+                ip: self.ip,
             })
             .into_actor(self)
             .then(|res, _, ctx| {
                 match res {
-                    Ok(_res) => (),
+                    Ok(Ok(())) => (),
+                    // The connection was refused, let the client know why before closing it
+                    Ok(Err(e)) => {
+                        let packet = PacketType::from(e);
+                        ctx.text(
+                            serde_json::to_string(&Envelope {
+                                seq: 0,
+                                packet: &packet,
+                            })
+                            .unwrap(),
+                        );
+                        ctx.close(None);
+                        ctx.stop();
+                    }
                     _ => ctx.stop(),
                 }
                 fut::ready(())
@@ -92,10 +122,9 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsConn {
             Ok(ws::Message::Pong(_)) => {
                 self.hb = Instant::now();
             }
-            Ok(ws::Message::Binary(bin)) => {
-                println!("Binary: {:#?}", &bin);
-                ctx.binary(bin);
-            }
+            Ok(ws::Message::Binary(bin)) => self
+                .lobby_addr
+                .do_send(Packet::from_binary(self.id, &bin, self.room)),
             Ok(ws::Message::Close(reason)) => {
                 ctx.close(reason);
                 ctx.stop();
@@ -115,6 +144,13 @@ impl Handler<WsMessage> for WsConn {
     type Result = ();
 
     fn handle(&mut self, msg: WsMessage, ctx: &mut Self::Context) {
-        ctx.text(msg.0);
+        match msg {
+            WsMessage::Text(text) => ctx.text(text),
+            WsMessage::Binary(bin) => ctx.binary(bin),
+            WsMessage::Close => {
+                ctx.close(None);
+                ctx.stop();
+            }
+        }
     }
 }
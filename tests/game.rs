@@ -0,0 +1,1129 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use uno_server::bot::BotStrategy;
+use uno_server::errors::OptionsError;
+use uno_server::game::{Action, Card, Color, Event, Game, Target, Type, MAX_PLAYERS};
+use uno_server::options::{DrawRule, GameOptions};
+use uno_server::packets::{PacketType, ScoreEntry};
+use uuid::Uuid;
+
+// Creates a game with two registered players, the first one being the host
+fn setup() -> (Game, Uuid, Uuid) {
+    let mut game = Game::new();
+    let (host, guest) = (Uuid::new_v4(), Uuid::new_v4());
+
+    game.join(host);
+    game.join(guest);
+    game.register(host, "host");
+    game.register(guest, "guest");
+
+    (game, host, guest)
+}
+
+// Starts a game with known hands. The guest always has the first turn.
+fn start_with(
+    options: GameOptions,
+    top: Card,
+    host_cards: Vec<Card>,
+    guest_cards: Vec<Card>,
+) -> (Game, Uuid, Uuid) {
+    let (mut game, host, guest) = setup();
+    let mut cards = vec![top];
+    cards.extend(host_cards);
+    cards.extend(guest_cards);
+
+    game.stack_deck(cards);
+    game.handle(host, Action::StartGame(options));
+    assert_eq!(game.current_turn, Some(guest));
+
+    (game, host, guest)
+}
+
+// Returns the code of the first error sent to the player
+fn error_code(events: &[Event], id: Uuid) -> Option<String> {
+    events.iter().find_map(|e| match &e.packet {
+        PacketType::Error { code, .. } if e.target == Target::Player(id) => Some(code.clone()),
+        _ => None,
+    })
+}
+
+fn has_error(events: &[Event], id: Uuid) -> bool {
+    events
+        .iter()
+        .any(|e| e.target == Target::Player(id) && matches!(e.packet, PacketType::Error { .. }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_host_can_start() {
+        let (mut game, host, guest) = setup();
+
+        let events = game.handle(guest, Action::StartGame(GameOptions::default()));
+        assert!(has_error(&events, guest));
+        assert!(!game.active);
+
+        let events = game.handle(host, Action::StartGame(GameOptions::default()));
+        assert!(!has_error(&events, host));
+        assert!(game.active);
+
+        // Every player receives their own hand privately
+        for id in [host, guest] {
+            assert!(events.iter().any(|e| e.target == Target::Player(id)
+                && matches!(&e.packet, PacketType::StatusUpdatePrivate { cards, .. } if cards.len() == 8)));
+        }
+    }
+
+    #[test]
+    fn rejects_actions_out_of_turn() {
+        let (mut game, host, guest) = setup();
+        game.handle(host, Action::StartGame(GameOptions::default()));
+
+        let current = game.current_turn.unwrap();
+        let other = if current == host { guest } else { host };
+
+        let events = game.handle(other, Action::DrawCard(1));
+        assert!(has_error(&events, other));
+        assert_eq!(game.get_player(&other).cards.len(), 8);
+    }
+
+    #[test]
+    fn turn_passes_after_drawing_three_cards() {
+        let (mut game, host, _) = setup();
+        game.handle(host, Action::StartGame(GameOptions::default()));
+
+        let current = game.current_turn.unwrap();
+
+        let events = game.handle(current, Action::EndTurn);
+        assert!(has_error(&events, current));
+
+        for _ in 0..3 {
+            game.handle(current, Action::DrawCard(1));
+        }
+
+        let events = game.handle(current, Action::EndTurn);
+        assert!(!has_error(&events, current));
+        assert_ne!(game.current_turn, Some(current));
+        assert!(events
+            .iter()
+            .any(|e| e.target == Target::All && matches!(e.packet, PacketType::TurnUpdate { .. })));
+    }
+
+    #[test]
+    fn drawing_three_cards_at_once_allows_ending_the_turn() {
+        let (mut game, host, _) = setup();
+        game.handle(host, Action::StartGame(GameOptions::default()));
+        let current = game.current_turn.unwrap();
+
+        let events = game.handle(current, Action::DrawCard(3));
+        assert!(!has_error(&events, current));
+        assert_eq!(game.get_player(&current).cards.len(), 11);
+
+        let events = game.handle(current, Action::DrawCard(1));
+        assert_eq!(
+            error_code(&events, current).as_deref(),
+            Some("ILLEGAL_DRAW")
+        );
+
+        let events = game.handle(current, Action::EndTurn);
+        assert!(!has_error(&events, current));
+        assert_ne!(game.current_turn, Some(current));
+    }
+
+    #[test]
+    fn seed_reproduces_the_deck() {
+        let (a, b) = (Game::with_seed(42), Game::with_seed(42));
+
+        assert_eq!(a.deck, b.deck);
+        assert_ne!(a.deck, Game::with_seed(43).deck);
+        assert_eq!(a.statistics.seed, 42);
+    }
+
+    #[test]
+    fn start_card_skips_action_cards() {
+        let (mut game, host, _) = setup();
+        game.stack_deck(vec![
+            Card::new(Type::DrawFour, Color::Red),
+            Card::new(Type::Block, Color::Blue),
+            Card::new(Type::Three, Color::Green),
+        ]);
+
+        game.handle(host, Action::StartGame(GameOptions::default()));
+
+        assert_eq!(
+            game.placed_deck.front(),
+            Some(&Card::new(Type::Three, Color::Green))
+        );
+        // Rejected cards are returned to the bottom of the deck
+        assert_eq!(
+            game.deck.iter().rev().take(2).collect::<Vec<&Card>>(),
+            vec![
+                &Card::new(Type::Block, Color::Blue),
+                &Card::new(Type::DrawFour, Color::Red)
+            ]
+        );
+    }
+
+    #[test]
+    fn players_are_dealt_from_the_deck() {
+        let (mut game, host, guest) = setup();
+        let mut cards = vec![Card::new(Type::Five, Color::Red)];
+        cards.extend((0..8).map(|_| Card::new(Type::One, Color::Blue)));
+        cards.extend((0..8).map(|_| Card::new(Type::Two, Color::Yellow)));
+
+        let deck_size = game.deck.len() + cards.len();
+        game.stack_deck(cards);
+        game.handle(host, Action::StartGame(GameOptions::default()));
+
+        assert!(game
+            .get_player(&host)
+            .cards
+            .iter()
+            .all(|c| c.r#type == Type::One && c.owner == Some(host)));
+        assert!(game
+            .get_player(&guest)
+            .cards
+            .iter()
+            .all(|c| c.r#type == Type::Two && c.owner == Some(guest)));
+        assert_eq!(game.deck.len(), deck_size - 17);
+    }
+
+    #[test]
+    fn placed_cards_are_recycled_when_the_deck_runs_out() {
+        let (mut game, host, _) = setup();
+        game.handle(host, Action::StartGame(GameOptions::default()));
+        let current = game.current_turn.unwrap();
+
+        let top = game.placed_deck.front().unwrap().clone();
+        let mut placed = Card::new(Type::Nine, Color::Blue);
+        placed.owner = Some(host);
+
+        game.deck.clear();
+        game.placed_deck
+            .extend(vec![placed.clone(), placed.clone()]);
+
+        let events = game.handle(current, Action::DrawCard(1));
+
+        assert!(events
+            .iter()
+            .any(|e| matches!(e.packet, PacketType::DeckReshuffled { deck_size: 2 })));
+        assert_eq!(game.placed_deck, vec![top]);
+        assert_eq!(game.deck, vec![Card::new(Type::Nine, Color::Blue)]);
+        assert_eq!(game.statistics.reshuffles, 1);
+    }
+
+    #[test]
+    fn exhausted_cards_report_a_deadlock() {
+        let (mut game, host, _) = setup();
+        game.handle(host, Action::StartGame(GameOptions::default()));
+        let current = game.current_turn.unwrap();
+
+        game.deck.clear();
+        let events = game.handle(current, Action::DrawCard(1));
+
+        assert!(events
+            .iter()
+            .any(|e| e.target == Target::All && matches!(e.packet, PacketType::Deadlock)));
+        assert_eq!(game.get_player(&current).cards.len(), 8);
+        assert!(game.statistics.deadlocked);
+
+        // The player is allowed to pass their turn as there's nothing to draw
+        let events = game.handle(current, Action::EndTurn);
+        assert!(!has_error(&events, current));
+    }
+
+    #[test]
+    fn options_are_parsed_and_validated() {
+        assert_eq!(GameOptions::parse("None"), Ok(GameOptions::default()));
+        assert_eq!(
+            GameOptions::parse(r#"{"hand_size": 0}"#),
+            Err(OptionsError::HandSize(0))
+        );
+        assert!(matches!(
+            GameOptions::parse(r#"{"hand_size": "seven"}"#),
+            Err(OptionsError::Malformed(_))
+        ));
+        assert_eq!(
+            GameOptions::parse(&format!(r#"{{"turn_timeout": {}}}"#, u64::MAX)),
+            Err(OptionsError::TurnTimeout)
+        );
+
+        let options = GameOptions::parse(r#"{"hand_size": 5, "jump_in": true}"#).unwrap();
+        assert_eq!(options.hand_size, 5);
+        assert!(options.jump_in && options.draw_stacking);
+
+        let (mut game, host, guest) = setup();
+        game.handle(host, Action::StartGame(options));
+        assert_eq!(game.get_player(&guest).cards.len(), 5);
+    }
+
+    #[test]
+    fn draw_stacking_can_be_disabled() {
+        let options = GameOptions {
+            draw_stacking: false,
+            ..GameOptions::default()
+        };
+        let mut top = Card::new(Type::DrawTwo, Color::Red);
+        top.owner = Some(Uuid::new_v4());
+        let hand = vec![
+            Card::new(Type::DrawTwo, Color::Blue),
+            Card::new(Type::Five, Color::Red),
+        ];
+
+        let allowed = Card::get_allowed_cards(top.clone(), hand.clone(), Uuid::new_v4(), &options);
+        assert!(allowed.is_empty());
+
+        let allowed = Card::get_allowed_cards(top, hand, Uuid::new_v4(), &GameOptions::default());
+        assert_eq!(allowed, vec![Card::new(Type::DrawTwo, Color::Blue)]);
+    }
+
+    #[test]
+    fn drawing_until_playable_allows_ending_the_turn() {
+        let options = GameOptions {
+            hand_size: 1,
+            draw_rule: DrawRule::UntilPlayable,
+            ..GameOptions::default()
+        };
+        let (mut game, _, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![Card::new(Type::One, Color::Blue)],
+            vec![Card::new(Type::One, Color::Blue)],
+        );
+        game.stack_deck(vec![
+            Card::new(Type::Two, Color::Blue),
+            Card::new(Type::Five, Color::Green),
+        ]);
+
+        game.handle(guest, Action::DrawCard(1));
+        let events = game.handle(guest, Action::EndTurn);
+        assert!(has_error(&events, guest));
+
+        game.handle(guest, Action::DrawCard(1));
+        let events = game.handle(guest, Action::EndTurn);
+        assert!(!has_error(&events, guest));
+    }
+
+    #[test]
+    fn placing_a_seven_swaps_hands() {
+        let options = GameOptions {
+            hand_size: 2,
+            seven_zero: true,
+            ..GameOptions::default()
+        };
+        let (mut game, host, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![
+                Card::new(Type::One, Color::Blue),
+                Card::new(Type::Two, Color::Blue),
+            ],
+            vec![
+                Card::new(Type::Seven, Color::Red),
+                Card::new(Type::Nine, Color::Green),
+            ],
+        );
+
+        game.handle(guest, Action::PlaceCard(0));
+        let events = game.handle(guest, Action::EndTurn);
+        assert!(has_error(&events, guest));
+
+        game.handle(guest, Action::SwapHands(host));
+        assert_eq!(game.get_player(&guest).cards.len(), 2);
+        assert_eq!(game.get_player(&host).cards[0].r#type, Type::Nine);
+        assert_eq!(game.get_player(&host).cards[0].owner, Some(host));
+
+        let events = game.handle(guest, Action::EndTurn);
+        assert!(!has_error(&events, guest));
+    }
+
+    #[test]
+    fn identical_cards_can_jump_in() {
+        let options = GameOptions {
+            hand_size: 2,
+            jump_in: true,
+            ..GameOptions::default()
+        };
+        let (mut game, host, _) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![
+                Card::new(Type::Five, Color::Red),
+                Card::new(Type::Two, Color::Blue),
+            ],
+            vec![
+                Card::new(Type::Six, Color::Red),
+                Card::new(Type::Nine, Color::Green),
+            ],
+        );
+
+        let events = game.handle(host, Action::PlaceCard(1));
+        assert!(has_error(&events, host));
+
+        let events = game.handle(host, Action::PlaceCard(0));
+        assert!(!has_error(&events, host));
+        assert_eq!(game.current_turn, Some(host));
+        assert_eq!(game.get_player(&host).cards.len(), 1);
+    }
+
+    #[test]
+    fn forgetting_to_call_uno_can_be_caught() {
+        let options = GameOptions {
+            hand_size: 2,
+            ..GameOptions::default()
+        };
+        let (mut game, host, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![
+                Card::new(Type::One, Color::Blue),
+                Card::new(Type::Two, Color::Blue),
+            ],
+            vec![
+                Card::new(Type::Five, Color::Blue),
+                Card::new(Type::Six, Color::Blue),
+            ],
+        );
+
+        // Nothing to catch yet
+        let events = game.handle(host, Action::CatchUno(guest));
+        assert!(has_error(&events, host));
+
+        game.handle(guest, Action::PlaceCard(0));
+        let events = game.handle(host, Action::CatchUno(guest));
+
+        assert!(events.iter().any(|e| e.target == Target::All
+            && matches!(e.packet, PacketType::UnoCaught { catcher: c, target: t, penalty: 2 } if c == host && t == guest)));
+        assert_eq!(game.get_player(&guest).cards.len(), 3);
+    }
+
+    #[test]
+    fn calling_uno_protects_from_being_caught() {
+        let options = GameOptions {
+            hand_size: 2,
+            ..GameOptions::default()
+        };
+        let (mut game, host, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![
+                Card::new(Type::One, Color::Blue),
+                Card::new(Type::Two, Color::Blue),
+            ],
+            vec![
+                Card::new(Type::Five, Color::Blue),
+                Card::new(Type::Six, Color::Blue),
+            ],
+        );
+
+        // Uno cannot be called with more than one card outside of your turn
+        let events = game.handle(host, Action::CallUno);
+        assert!(has_error(&events, host));
+
+        let events = game.handle(guest, Action::CallUno);
+        assert!(events
+            .iter()
+            .any(|e| matches!(e.packet, PacketType::UnoCalled { id } if id == guest)));
+
+        game.handle(guest, Action::PlaceCard(0));
+        let events = game.handle(host, Action::CatchUno(guest));
+        assert!(has_error(&events, host));
+        assert_eq!(game.get_player(&guest).cards.len(), 1);
+    }
+
+    // Guest places a draw-four on a red five and passes the turn to the host
+    fn place_draw_four(second_card: Card) -> (Game, Uuid, Uuid) {
+        let options = GameOptions {
+            hand_size: 2,
+            ..GameOptions::default()
+        };
+        let (mut game, host, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![
+                Card::new(Type::One, Color::Blue),
+                Card::new(Type::Two, Color::Blue),
+            ],
+            vec![Card::new(Type::DrawFour, Color::Yellow), second_card],
+        );
+
+        game.handle(guest, Action::PlaceCard(0));
+        game.handle(guest, Action::ColorSwitch(Color::Green));
+        game.handle(guest, Action::EndTurn);
+        assert_eq!(game.current_turn, Some(host));
+
+        (game, host, guest)
+    }
+
+    #[test]
+    fn legal_draw_four_challenge_penalizes_the_challenger() {
+        let (mut game, host, guest) = place_draw_four(Card::new(Type::Six, Color::Blue));
+
+        let events = game.handle(host, Action::ChallengeDrawFour);
+
+        assert!(events.iter().any(|e| e.target == Target::Player(host)
+            && matches!(&e.packet, PacketType::ChallengeReveal { offender: id, cards } if *id == guest && cards.len() == 2)));
+        assert!(!events.iter().any(|e| e.target != Target::Player(host)
+            && matches!(e.packet, PacketType::ChallengeReveal { .. })));
+        assert!(events.iter().any(|e| matches!(
+            e.packet,
+            PacketType::ChallengeResult {
+                successful: false,
+                ..
+            }
+        )));
+        assert_eq!(game.get_player(&host).cards.len(), 8);
+        assert_eq!(game.current_turn, Some(guest));
+    }
+
+    #[test]
+    fn illegal_draw_four_challenge_penalizes_the_offender() {
+        let (mut game, host, guest) = place_draw_four(Card::new(Type::Six, Color::Red));
+
+        let events = game.handle(host, Action::ChallengeDrawFour);
+
+        assert!(events.iter().any(|e| matches!(
+            e.packet,
+            PacketType::ChallengeResult {
+                successful: true,
+                ..
+            }
+        )));
+        assert_eq!(game.get_player(&guest).cards.len(), 5);
+        assert_eq!(game.get_player(&host).cards.len(), 2);
+        assert_eq!(game.current_turn, Some(host));
+
+        // The draw-four cannot be challenged twice
+        let events = game.handle(host, Action::ChallengeDrawFour);
+        assert!(has_error(&events, host));
+    }
+
+    #[test]
+    fn cards_are_worth_official_points() {
+        assert_eq!(Card::new(Type::Seven, Color::Red).points(), 7);
+        assert_eq!(Card::new(Type::Reverse, Color::Red).points(), 20);
+        assert_eq!(Card::new(Type::DrawFour, Color::Red).points(), 50);
+    }
+
+    // Guest wins the first round while the host is left with a draw-four
+    fn win_round(target_score: usize) -> (Game, Vec<Event>, Uuid, Uuid) {
+        let options = GameOptions {
+            hand_size: 1,
+            target_score: Some(target_score),
+            ..GameOptions::default()
+        };
+        let (mut game, host, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![Card::new(Type::DrawFour, Color::Blue)],
+            vec![Card::new(Type::Five, Color::Blue)],
+        );
+
+        game.handle(guest, Action::PlaceCard(0));
+        let events = game.handle(guest, Action::EndTurn);
+
+        (game, events, host, guest)
+    }
+
+    #[test]
+    fn match_continues_until_the_target_score() {
+        let (game, events, host, guest) = win_round(500);
+
+        assert!(events.iter().any(|e| matches!(&e.packet,
+            PacketType::MatchUpdate { round: 1, scores, winner: None } if scores.contains(&ScoreEntry { id: guest, username: "guest".to_string(), score: 50 }))));
+        assert!(game.active);
+        assert_eq!(game.round, 2);
+        assert_eq!(game.scores.get(&guest), Some(&50));
+        assert_eq!(game.get_player(&host).cards.len(), 1);
+        assert_eq!(game.get_player(&guest).cards.len(), 1);
+        // The first turn moves to the next player
+        assert_eq!(game.current_turn, Some(host));
+    }
+
+    #[test]
+    fn match_ends_when_the_target_score_is_reached() {
+        let (game, events, _, guest) = win_round(50);
+
+        assert!(events
+            .iter()
+            .any(|e| matches!(&e.packet, PacketType::MatchUpdate { round: 1, winner: Some(id), .. } if *id == guest)));
+        assert!(!game.active);
+    }
+
+    #[test]
+    fn a_new_match_starts_from_scratch() {
+        let (mut game, _, host, guest) = win_round(50);
+
+        let options = GameOptions {
+            target_score: Some(50),
+            ..GameOptions::default()
+        };
+        game.handle(host, Action::StartGame(options));
+        assert!(game.active);
+        assert_eq!(game.round, 1);
+        assert!(game.scores.is_empty());
+        assert_eq!(game.placed_deck.len(), 1);
+        assert_eq!(game.get_player(&host).cards.len(), 8);
+        assert_eq!(game.get_player(&guest).cards.len(), 8);
+        assert_eq!(
+            game.deck.len() + game.placed_deck.len() + 16,
+            Game::new().deck.len()
+        );
+    }
+
+    #[test]
+    fn timed_out_turns_are_played_by_the_server() {
+        let options = GameOptions {
+            turn_timeout: Some(30),
+            ..GameOptions::default()
+        };
+        let (mut game, host, guest) = setup();
+        let events = game.handle(host, Action::StartGame(options));
+
+        assert!(events.iter().any(|e| matches!(
+            e.packet,
+            PacketType::TurnUpdate { current: id, deadline: Some(_), .. } if id == guest
+        )));
+
+        let turn = game.turn;
+        game.timeout(turn);
+
+        assert_eq!(game.get_player(&guest).cards.len(), 11);
+        assert_eq!(game.current_turn, Some(host));
+
+        // Timers of turns that have already ended are ignored
+        game.timeout(turn);
+        assert_eq!(game.current_turn, Some(host));
+    }
+
+    #[test]
+    fn timed_out_wild_cards_get_a_color() {
+        let options = GameOptions {
+            turn_timeout: Some(30),
+            ..GameOptions::default()
+        };
+        let (mut game, host, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![Card::new(Type::One, Color::Red); 8],
+            vec![
+                Card::new(Type::Switch, Color::Red),
+                Card::new(Type::Two, Color::Green),
+                Card::new(Type::Three, Color::Green),
+                Card::new(Type::Four, Color::Blue),
+                Card::new(Type::Six, Color::Yellow),
+                Card::new(Type::Seven, Color::Red),
+                Card::new(Type::Eight, Color::Green),
+                Card::new(Type::Nine, Color::Blue),
+            ],
+        );
+
+        game.handle(guest, Action::PlaceCard(0));
+        game.timeout(game.turn);
+
+        assert_eq!(game.current_turn, Some(host));
+        assert_eq!(game.placed_deck.front().unwrap().color, Color::Green);
+    }
+
+    #[test]
+    fn repeated_timeouts_mark_players_afk_and_remove_them() {
+        let options = GameOptions {
+            turn_timeout: Some(30),
+            max_timeouts: 3,
+            ..GameOptions::default()
+        };
+        let (mut game, host, guest) = setup();
+        game.handle(host, Action::StartGame(options));
+
+        // Turns alternate between the guest and the host
+        game.timeout(game.turn);
+        game.timeout(game.turn);
+        let events = game.timeout(game.turn);
+        assert!(events
+            .iter()
+            .any(|e| matches!(e.packet, PacketType::PlayerAfk { id, afk: true } if id == guest)));
+        game.timeout(game.turn);
+        assert!(game.get_player(&host).afk && game.get_player(&guest).afk);
+
+        // Guest times out for the third time and the game can't continue with one player
+        let events = game.timeout(game.turn);
+        assert!(events
+            .iter()
+            .any(|e| matches!(e.packet, PacketType::Disconnect { id, .. } if id == guest)));
+        assert!(!game.players.contains_key(&guest));
+        assert!(!game.active);
+    }
+
+    #[test]
+    fn disconnected_players_keep_their_seat() {
+        let (mut game, host, guest) = setup();
+        game.handle(host, Action::StartGame(GameOptions::default()));
+        let hand = game.get_player(&guest).cards.clone();
+
+        let events = game.disconnect(guest);
+        assert!(game.active);
+        assert!(!game.get_player(&guest).is_connected);
+        assert!(events.iter().any(|e| e.target == Target::AllExcept(guest)
+            && matches!(e.packet, PacketType::ConnectionUpdate { id, connected: false } if id == guest)));
+
+        // Resuming sends the hand, the other hands and the turn order
+        let events = game.reconnect(guest);
+        assert!(game.get_player(&guest).is_connected);
+        assert!(events.iter().any(|e| e.target == Target::Player(guest)
+            && matches!(&e.packet, PacketType::Snapshot { hand: cards, opponents, current, .. }
+                if *cards == hand
+                    && opponents.iter().any(|o| o.id == host && o.cards == 8)
+                    && Some(*current) == game.current_turn)));
+    }
+
+    #[test]
+    fn snapshots_describe_the_whole_game() {
+        let options = GameOptions {
+            hand_size: 2,
+            ..GameOptions::default()
+        };
+        let (mut game, host, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![
+                Card::new(Type::Three, Color::Blue),
+                Card::new(Type::Four, Color::Blue),
+            ],
+            vec![
+                Card::new(Type::Five, Color::Blue),
+                Card::new(Type::Two, Color::Green),
+            ],
+        );
+        let deck_size = game.deck.len();
+
+        let events = game.snapshot(guest);
+        let snapshot = events
+            .iter()
+            .find_map(|e| match &e.packet {
+                PacketType::Snapshot { .. } if e.target == Target::Player(guest) => {
+                    Some(e.packet.clone())
+                }
+                _ => None,
+            })
+            .unwrap();
+
+        let PacketType::Snapshot {
+            turn_order,
+            current,
+            next,
+            reversed,
+            top,
+            color,
+            opponents,
+            hand,
+            allowed,
+            deck_size: size,
+            ..
+        } = snapshot
+        else {
+            unreachable!()
+        };
+        assert_eq!(turn_order.len(), 2);
+        assert_eq!(current, guest);
+        assert_eq!(next, host);
+        assert!(!reversed);
+        assert_eq!(top, Card::new(Type::Five, Color::Red));
+        assert_eq!(color, Color::Red);
+        assert_eq!(opponents.len(), 1);
+        assert_eq!(opponents[0].cards, 2);
+        assert_eq!(hand.len(), 2);
+        assert_eq!(allowed, vec![hand[0].clone()]);
+        assert_eq!(size, deck_size);
+    }
+
+    #[test]
+    fn game_continues_when_a_player_leaves() {
+        let (mut game, host, guest) = setup();
+        let third = Uuid::new_v4();
+        game.join(third);
+        game.register(third, "third");
+        game.handle(host, Action::StartGame(GameOptions::default()));
+
+        let current = game.current_turn.unwrap();
+        game.leave(current);
+        assert!(game.active);
+        assert_eq!(game.players.len(), 2);
+        assert!(game.current_turn.is_some_and(|id| id != current));
+
+        let remaining = game.current_turn.unwrap();
+        game.leave(remaining);
+        assert!(!game.active);
+        assert!([host, guest, third].contains(&remaining));
+    }
+
+    #[test]
+    fn spectators_only_receive_public_events() {
+        let (mut game, host, _) = setup();
+        game.handle(host, Action::StartGame(GameOptions::default()));
+
+        let spectator = Uuid::new_v4();
+        game.spectate(spectator);
+        let events = game.register(spectator, "spectator");
+        assert!(!game.players.contains_key(&spectator));
+        assert!(events.iter().any(|e| e.target == Target::Player(spectator)
+            && matches!(&e.packet, PacketType::Snapshot { hand, allowed, opponents, .. }
+                if hand.is_empty()
+                    && allowed.is_empty()
+                    && opponents.iter().any(|o| o.id == host && o.cards == 8))));
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e.packet, PacketType::StatusUpdatePrivate { .. })));
+
+        // Actions of spectators are ignored
+        let events = game.handle(spectator, Action::DrawCard(1));
+        assert!(events.is_empty());
+
+        // Private hands are only sent to their owner
+        let current = game.current_turn.unwrap();
+        let events = game.handle(current, Action::DrawCard(1));
+        assert!(events.iter().all(|e| e.target != Target::Player(spectator)));
+        assert!(events
+            .iter()
+            .filter(|e| matches!(e.packet, PacketType::StatusUpdatePrivate { .. }))
+            .all(|e| e.target == Target::Player(current)));
+    }
+
+    #[test]
+    fn spectators_take_a_seat_when_the_next_round_begins() {
+        let options = GameOptions {
+            hand_size: 1,
+            target_score: Some(500),
+            ..GameOptions::default()
+        };
+        let (mut game, _, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            vec![Card::new(Type::DrawFour, Color::Blue)],
+            vec![Card::new(Type::Five, Color::Blue)],
+        );
+
+        let spectator = Uuid::new_v4();
+        game.spectate(spectator);
+        let events = game.take_seat(spectator);
+        assert!(has_error(&events, spectator));
+
+        game.register(spectator, "spectator");
+        game.take_seat(spectator);
+        assert!(!game.players.contains_key(&spectator));
+
+        game.handle(guest, Action::PlaceCard(0));
+        game.handle(guest, Action::EndTurn);
+
+        assert_eq!(game.round, 2);
+        assert!(game.spectators.is_empty());
+        assert_eq!(game.get_player(&spectator).cards.len(), 1);
+    }
+
+    #[test]
+    fn spectators_cannot_take_a_seat_in_a_full_room() {
+        let (mut game, _, _) = setup();
+        game.max_players = 2;
+
+        let spectator = Uuid::new_v4();
+        game.join(spectator);
+        game.register(spectator, "spectator");
+
+        let events = game.take_seat(spectator);
+        assert_eq!(error_code(&events, spectator).as_deref(), Some("ROOM_FULL"));
+        assert!(game.spectators.contains_key(&spectator));
+    }
+
+    #[test]
+    fn spectators_are_seated_after_a_finished_game() {
+        let (mut game, _, _, _) = win_round(50);
+        assert!(!game.active);
+        // The leftover deck is not used for the next game
+        game.deck.truncate(1);
+
+        let spectator = Uuid::new_v4();
+        game.spectate(spectator);
+        game.register(spectator, "spectator");
+        let events = game.take_seat(spectator);
+        assert!(!has_error(&events, spectator));
+        assert!(game.players.contains_key(&spectator));
+    }
+
+    #[test]
+    fn spectators_are_told_when_the_options_leave_no_cards() {
+        let (mut game, _, _) = setup();
+        game.options.hand_size = GameOptions::MAX_HAND_SIZE;
+        for _ in 2..5 {
+            let id = Uuid::new_v4();
+            game.join(id);
+            game.register(id, "player");
+        }
+
+        let spectator = Uuid::new_v4();
+        game.spectate(spectator);
+        game.register(spectator, "spectator");
+        let events = game.take_seat(spectator);
+        assert_eq!(
+            error_code(&events, spectator).as_deref(),
+            Some("INVALID_OPTIONS")
+        );
+        assert!(game.spectators[&spectator].waiting);
+    }
+
+    #[test]
+    fn only_the_host_can_add_bots_before_the_game() {
+        let (mut game, host, guest) = setup();
+
+        let events = game.handle(guest, Action::AddBot(BotStrategy::Random));
+        assert!(has_error(&events, guest));
+
+        let events = game.handle(host, Action::AddBot(BotStrategy::Greedy));
+        let bot = events
+            .iter()
+            .find_map(|e| match e.packet {
+                PacketType::Connect { id, .. } => Some(id),
+                _ => None,
+            })
+            .unwrap();
+        assert!(game.is_bot(&bot));
+        assert_eq!(game.players.len(), 3);
+
+        game.handle(host, Action::RemoveBot(bot));
+        assert_eq!(game.players.len(), 2);
+
+        game.handle(host, Action::AddBot(BotStrategy::Color));
+        game.handle(host, Action::StartGame(GameOptions::default()));
+        let events = game.handle(host, Action::AddBot(BotStrategy::Random));
+        assert!(has_error(&events, host));
+    }
+
+    #[test]
+    fn bots_can_be_added_after_a_finished_game() {
+        let (mut game, _, host, _) = win_round(50);
+        assert!(!game.active);
+        // The leftover deck is not used for the next game
+        game.deck.truncate(1);
+
+        let events = game.handle(host, Action::AddBot(BotStrategy::Random));
+        assert!(!has_error(&events, host));
+        assert_eq!(game.players.len(), 3);
+    }
+
+    #[test]
+    fn bots_play_their_turns() {
+        let mut game = Game::with_seed(7);
+        let host = Uuid::new_v4();
+        game.join(host);
+        game.register(host, "host");
+
+        for strategy in [BotStrategy::Random, BotStrategy::Greedy, BotStrategy::Color] {
+            game.handle(host, Action::AddBot(strategy));
+        }
+        game.handle(host, Action::StartGame(GameOptions::default()));
+
+        // The host plays with the same decisions as a bot, every other turn is played by the server
+        let mut host_strategy = BotStrategy::Random.create();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _ in 0..10_000 {
+            if !game.active {
+                break;
+            }
+            assert_eq!(game.current_turn, Some(host));
+
+            let action = host_strategy.decide(&game.bot_view(&host), &mut rng);
+            let events = game.handle(host, action);
+            assert!(!has_error(&events, host));
+        }
+
+        assert!(!game.active);
+        assert!(game.statistics.cards_placed > 0);
+    }
+
+    #[test]
+    fn illegal_actions_are_rejected() {
+        let options = GameOptions {
+            hand_size: 3,
+            ..GameOptions::default()
+        };
+        let (mut game, _, guest) = start_with(
+            options,
+            Card::new(Type::Five, Color::Red),
+            (0..3).map(|_| Card::new(Type::One, Color::Blue)).collect(),
+            vec![
+                Card::new(Type::Two, Color::Blue),
+                Card::new(Type::Switch, Color::Red),
+                Card::new(Type::Three, Color::Red),
+            ],
+        );
+
+        for action in [
+            Action::PlaceCard(42),
+            Action::PlaceCard(0),
+            Action::DrawCard(0),
+            Action::DrawCard(4),
+            Action::ColorSwitch(Color::Green),
+        ] {
+            let events = game.handle(guest, action);
+            assert!(has_error(&events, guest));
+        }
+        assert_eq!(game.get_player(&guest).cards.len(), 3);
+        assert_eq!(game.placed_deck.len(), 1);
+
+        // The color of a wild card can only be chosen once
+        game.handle(guest, Action::PlaceCard(1));
+        let events = game.handle(guest, Action::ColorSwitch(Color::Green));
+        assert!(!has_error(&events, guest));
+        let events = game.handle(guest, Action::ColorSwitch(Color::Blue));
+        assert!(has_error(&events, guest));
+        assert_eq!(game.placed_deck.front().unwrap().color, Color::Green);
+    }
+
+    #[test]
+    fn errors_have_stable_codes() {
+        let (mut game, host, guest) = setup();
+
+        let events = game.handle(guest, Action::StartGame(GameOptions::default()));
+        assert_eq!(error_code(&events, guest).as_deref(), Some("NOT_HOST"));
+
+        let events = game.register(guest, "guest");
+        assert_eq!(
+            error_code(&events, guest).as_deref(),
+            Some("ALREADY_REGISTERED")
+        );
+
+        game.handle(host, Action::StartGame(GameOptions::default()));
+        let other = if game.current_turn == Some(host) {
+            guest
+        } else {
+            host
+        };
+        let events = game.handle(other, Action::EndTurn);
+        assert_eq!(error_code(&events, other).as_deref(), Some("NOT_YOUR_TURN"));
+
+        let events = game.handle(host, Action::StartGame(GameOptions::default()));
+        assert_eq!(
+            error_code(&events, host).as_deref(),
+            Some("GAME_IN_PROGRESS")
+        );
+    }
+
+    #[test]
+    fn only_the_host_can_kick_players() {
+        let (mut game, host, guest) = setup();
+        let spectator = Uuid::new_v4();
+        game.spectate(spectator);
+
+        let events = game.handle(guest, Action::Kick(host));
+        assert_eq!(error_code(&events, guest).as_deref(), Some("NOT_HOST"));
+        let events = game.handle(host, Action::Kick(host));
+        assert_eq!(error_code(&events, host).as_deref(), Some("ILLEGAL_ACTION"));
+        let events = game.handle(host, Action::Kick(Uuid::new_v4()));
+        assert_eq!(error_code(&events, host).as_deref(), Some("UNKNOWN_PLAYER"));
+
+        let events = game.handle(host, Action::Kick(spectator));
+        assert!(!game.spectators.contains_key(&spectator));
+        assert!(events.iter().any(|e| e.target == Target::All
+            && matches!(e.packet, PacketType::Kicked { id, banned: false } if id == spectator)));
+
+        // The target is told before being removed
+        let events = game.handle(host, Action::Ban(guest));
+        assert!(!game.players.contains_key(&guest));
+        let kicked = events
+            .iter()
+            .position(
+                |e| matches!(e.packet, PacketType::Kicked { id, banned: true } if id == guest),
+            )
+            .unwrap();
+        let disconnect = events
+            .iter()
+            .position(|e| matches!(e.packet, PacketType::Disconnect { id, .. } if id == guest))
+            .unwrap();
+        assert!(kicked < disconnect);
+    }
+
+    #[test]
+    fn kicking_a_player_keeps_the_game_going() {
+        let (mut game, host, guest) = setup();
+        let third = Uuid::new_v4();
+        game.join(third);
+        game.register(third, "third");
+        game.handle(host, Action::StartGame(GameOptions::default()));
+
+        let current = game.current_turn.unwrap();
+        let target = if current == host { guest } else { current };
+        game.handle(host, Action::Kick(target));
+        assert!(game.active);
+        assert_eq!(game.players.len(), 2);
+        assert!(game.current_turn.is_some_and(|id| id != target));
+    }
+
+    #[test]
+    fn host_can_be_transferred() {
+        let (mut game, host, guest) = setup();
+
+        let events = game.handle(guest, Action::TransferHost(guest));
+        assert_eq!(error_code(&events, guest).as_deref(), Some("NOT_HOST"));
+
+        let events = game.handle(host, Action::TransferHost(guest));
+        assert!(events.iter().any(|e| e.target == Target::All
+            && matches!(e.packet, PacketType::HostChanged { id } if id == guest)));
+        assert!(game.is_host(&guest));
+        assert!(!game.is_host(&host));
+
+        let events = game.handle(host, Action::StartGame(GameOptions::default()));
+        assert_eq!(error_code(&events, host).as_deref(), Some("NOT_HOST"));
+
+        // Bots can't be the host
+        let events = game.handle(guest, Action::AddBot(BotStrategy::Random));
+        let bot = events
+            .iter()
+            .find_map(|e| match e.packet {
+                PacketType::Connect { id, .. } => Some(id),
+                _ => None,
+            })
+            .unwrap();
+        let events = game.handle(guest, Action::TransferHost(bot));
+        assert_eq!(
+            error_code(&events, guest).as_deref(),
+            Some("ILLEGAL_ACTION")
+        );
+        assert!(game.is_host(&guest));
+    }
+
+    #[test]
+    fn host_passes_on_when_the_host_leaves() {
+        let (mut game, host, guest) = setup();
+        let third = Uuid::new_v4();
+        game.join(third);
+        game.register(third, "third");
+
+        let events = game.leave(host);
+        assert!(events.iter().any(|e| e.target == Target::All
+            && matches!(e.packet, PacketType::HostChanged { id } if id == guest)));
+        assert!(game.is_host(&guest));
+
+        // A disconnected host passes it on and doesn't get it back when resuming
+        game.handle(guest, Action::StartGame(GameOptions::default()));
+        game.disconnect(guest);
+        assert!(game.is_host(&third));
+        game.reconnect(guest);
+        assert!(game.is_host(&third));
+        assert!(!game.is_host(&guest));
+    }
+
+    #[test]
+    fn full_rooms_reject_new_players() {
+        let (mut game, _, _) = setup();
+        for _ in 2..MAX_PLAYERS {
+            game.join(Uuid::new_v4());
+        }
+
+        let late = Uuid::new_v4();
+        let events = game.join(late);
+        assert_eq!(error_code(&events, late).as_deref(), Some("ROOM_FULL"));
+        assert_eq!(game.players.len(), MAX_PLAYERS);
+        assert!(game.spectators.contains_key(&late));
+    }
+}
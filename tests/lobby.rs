@@ -1,40 +1,61 @@
 use actix::Actor;
-use actix_web::rt::net::TcpStream;
+use actix_rt::time::{sleep, timeout};
 use colored::Colorize;
-use futures_util::stream::SplitSink;
-use futures_util::{FutureExt, SinkExt, StreamExt};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
 use serde_json::Value;
 use std::net::TcpListener;
+use std::time::Duration;
 use uno_server::lobby::Lobby;
+use uno_server::matchmaking::matchmaking;
+use uno_server::rooms::{create_invite, create_room, delete_room, get_room, list_rooms};
 use uno_server::start_connection::start_connection as start_connection_route;
 use uuid::Uuid;
 
-use actix_web::{App, HttpServer};
-use tokio_tungstenite::{
-    connect_async, tungstenite::protocol::Message, tungstenite::Error, MaybeTlsStream,
-    WebSocketStream,
-};
+use actix_web::http::StatusCode;
+use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+use actix_web::{web::Data, App, HttpServer};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, tungstenite::Error};
 
 #[derive(Debug, Clone)]
 struct Response {
     pub r#type: String,
+    pub request_id: Option<String>,
+    pub seq: u64,
+    pub data: Value,
 }
 
 impl Response {
-    pub fn new(r#type: String) -> Response {
-        Response { r#type }
+    pub fn new(r#type: String, request_id: Option<String>, seq: u64, data: Value) -> Response {
+        Response {
+            r#type,
+            request_id,
+            seq,
+            data,
+        }
     }
 }
 
 fn start_server() -> (u16, actix_web::rt::task::JoinHandle<()>) {
+    start_server_with(Lobby::default())
+}
+
+fn start_server_with(lobby: Lobby) -> (u16, actix_web::rt::task::JoinHandle<()>) {
     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
     let port = listener.local_addr().unwrap().port();
 
-    let handle = actix_rt::spawn(async {
-        HttpServer::new(|| {
-            let chat_server = Lobby::default().start();
+    let chat_server = lobby.start();
 
-            App::new().service(start_connection_route).data(chat_server)
+    let handle = actix_rt::spawn(async move {
+        HttpServer::new(move || {
+            App::new()
+                .service(create_room)
+                .service(list_rooms)
+                .service(get_room)
+                .service(delete_room)
+                .service(create_invite)
+                .service(matchmaking)
+                .service(start_connection_route)
+                .app_data(Data::new(chat_server.clone()))
         })
         .listen(listener)
         .unwrap()
@@ -66,7 +87,13 @@ fn log_message(data: Option<Result<Message, Error>>) -> Option<Response> {
                 println!("{}", format!("{:#?}", result).yellow());
             }
             */
-            Some(Response::new(r#type.as_str().unwrap().to_string()))
+            let request_id = result["data"]["request_id"].as_str().map(String::from);
+            Some(Response::new(
+                r#type.as_str().unwrap().to_string(),
+                request_id,
+                result["seq"].as_u64().unwrap(),
+                result["data"].clone(),
+            ))
         }
         Err(e) => {
             dbg!(format!("Failed to convert response to json: {}", e).red());
@@ -75,6 +102,35 @@ fn log_message(data: Option<Result<Message, Error>>) -> Option<Response> {
     }
 }
 
+// Announces the protocol version, which has to be the first packet of a connection
+async fn hello<S>(write: &mut S)
+where
+    S: Sink<Message, Error = Error> + Unpin,
+{
+    write
+        .send(Message::Text(
+            r#"{"type": "Hello", "data": {"version": 2}}"#.to_string(),
+        ))
+        .await
+        .unwrap();
+}
+
+// Reads messages until the server has been quiet for a moment
+async fn read_responses<S>(read: &mut S) -> Vec<Response>
+where
+    S: Stream<Item = Result<Message, Error>> + Unpin,
+{
+    let mut responses: Vec<Response> = Vec::new();
+
+    while let Ok(Some(message)) = timeout(Duration::from_millis(250), read.next()).await {
+        if let Some(r) = log_message(Some(message)) {
+            responses.push(r);
+        }
+    }
+
+    responses
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -89,7 +145,7 @@ mod tests {
                 .await
                 .unwrap();
 
-            let (mut write, mut read) = ws_stream.split();
+            let (_write, _read) = ws_stream.split();
         });
 
         client_handle.await?;
@@ -103,32 +159,39 @@ mod tests {
 
         // cargo test -- --nocapture
         let client_handle = actix_web::rt::spawn(async move {
+            let room_id = Uuid::new_v4();
+
             // Initialize client
-            let (player_1, _) = connect_async(format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4()))
+            let (player_1, _) = connect_async(format!("ws://127.0.0.1:{port}/{room_id}"))
                 .await
                 .unwrap();
 
             let (mut p_write_1, mut p_read_1) = player_1.split();
+            hello(&mut p_write_1).await;
 
             // Initialize client
-            let (player_2, _) = connect_async(format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4()))
+            let (player_2, _) = connect_async(format!("ws://127.0.0.1:{port}/{room_id}"))
                 .await
                 .unwrap();
 
             let (mut p_write_2, mut p_read_2) = player_2.split();
+            hello(&mut p_write_2).await;
 
             // Send Request - Register
             p_write_1
                 .send(Message::Text(
-                    r#"{"type": "REGISTER", "username": "test_1" }"#.to_string(),
+                    r#"{"type": "Register", "data": {"username": "test_1"}}"#.to_string(),
                 ))
                 .await
                 .unwrap();
 
+            // Wait for player 1 to become the host
+            read_responses(&mut p_read_1).await;
+
             // Send Request - Register
             p_write_2
                 .send(Message::Text(
-                    r#"{"type": "REGISTER", "username": "test_2"}"#.to_string(),
+                    r#"{"type": "Register", "data": {"username": "test_2"}}"#.to_string(),
                 ))
                 .await
                 .unwrap();
@@ -136,7 +199,7 @@ mod tests {
             // Send Request - Register a second time
             p_write_2
                 .send(Message::Text(
-                    r#"{"type": "REGISTER", "username": "test_2"}"#.to_string(),
+                    r#"{"type": "Register", "data": {"username": "test_2"}}"#.to_string(),
                 ))
                 .await
                 .unwrap();
@@ -144,61 +207,52 @@ mod tests {
             // Send Request - Start the game without permission
             p_write_2
                 .send(Message::Text(
-                    r#"{"type": "START-GAME","options": "None"}"#.to_string(),
+                    r#"{"type": "StartGame", "data": {}}"#.to_string(),
                 ))
                 .await
                 .unwrap();
 
-            let mut responses: Vec<Response> = Vec::new();
-
             // Read player 2's responses
-            while let Some(message) = p_read_2.next().now_or_never() {
-                if let Some(r) = log_message(message) {
-                    responses.push(r);
-                }
-            }
+            let mut responses = read_responses(&mut p_read_2).await;
 
             assert!(
-                responses.iter().any(|res| res.r#type == "ERROR"),
+                responses.iter().any(|res| res.r#type == "Error"),
                 "Two instances were created withour emiting an error and (or) game was started without permission"
             );
 
             // reset responses
             responses.clear();
+            read_responses(&mut p_read_1).await;
 
             // Send Request - Start game with permissio
             p_write_1
                 .send(Message::Text(
-                    r#"{"type": "START-GAME","options": "None"}"#.to_string(),
+                    r#"{"type": "StartGame", "data": {}}"#.to_string(),
                 ))
                 .await
                 .unwrap();
 
             // Read player 1's responses
-            while let Some(message) = p_read_1.next().now_or_never() {
-                if let Some(r) = log_message(message) {
-                    responses.push(r);
-                }
-            }
+            responses.extend(read_responses(&mut p_read_1).await);
 
             // dbg!(&responses);
 
             assert!(
-                !responses.iter().any(|res| res.r#type == "ERROR"),
+                !responses.iter().any(|res| res.r#type == "Error"),
                 "Error was emitted when trying to start the game"
             );
 
             assert!(
-                !responses
+                responses
                     .iter()
-                    .any(|res| res.r#type == "STATUS-UPDATE-PRIVATE"),
+                    .any(|res| res.r#type == "StatusUpdatePrivate"),
                 "Failed to receive your initial cards"
             );
 
             assert!(
-                !responses
+                responses
                     .iter()
-                    .any(|res| res.r#type == "STATUS-UPDATE-PRIVATE"),
+                    .any(|res| res.r#type == "StatusUpdatePublic"),
                 "Failed to receive the public cards"
             );
         });
@@ -207,4 +261,980 @@ mod tests {
         drop(server_handle);
         Ok(())
     }
+
+    #[actix_rt::test]
+    async fn malformed_packets_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let (player, _) = connect_async(format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4()))
+                .await
+                .unwrap();
+
+            let (mut write, mut read) = player.split();
+            hello(&mut write).await;
+            read_responses(&mut read).await;
+
+            // Send Request - Invalid json
+            write
+                .send(Message::Text(r#"{"type": "Register""#.to_string()))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+
+            assert!(
+                responses.iter().any(|res| res.r#type == "Error"),
+                "Malformed packet was ignored silently"
+            );
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn hello_negotiates_the_protocol() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let (player, _) = connect_async(format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4()))
+                .await
+                .unwrap();
+
+            let (mut write, mut read) = player.split();
+            read_responses(&mut read).await;
+
+            // Send Request - Supported version
+            write
+                .send(Message::Text(
+                    r#"{"type": "Hello", "data": {"version": 2, "capabilities": []}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+            assert!(
+                responses.iter().any(|res| res.r#type == "Welcome"),
+                "Server didn't answer the handshake"
+            );
+
+            // Send Request - Version with positional payloads
+            write
+                .send(Message::Text(
+                    r#"{"type": "Hello", "data": {"version": 1}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+            assert!(
+                responses.iter().any(|res| res.r#type == "Error"),
+                "Incompatible client was accepted"
+            );
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn incompatible_clients_are_disconnected() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let (player, _) = connect_async(format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4()))
+                .await
+                .unwrap();
+
+            let (mut write, mut read) = player.split();
+            read_responses(&mut read).await;
+
+            // Send Request - Version newer than the server's
+            write
+                .send(Message::Text(
+                    r#"{"type": "Hello", "data": {"version": 3}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+            assert!(responses
+                .iter()
+                .any(|res| res.r#type == "Error" && res.data["code"] == "INCOMPATIBLE_VERSION"));
+            assert!(
+                matches!(
+                    timeout(Duration::from_millis(250), read.next()).await,
+                    Ok(None | Some(Ok(Message::Close(_))))
+                ),
+                "Incompatible client wasn't disconnected"
+            );
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn clients_must_say_hello_first() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let (player, _) = connect_async(format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4()))
+                .await
+                .unwrap();
+
+            let (mut write, mut read) = player.split();
+            read_responses(&mut read).await;
+
+            // Send Request - Register without announcing a version
+            write
+                .send(Message::Text(
+                    r#"{"type": "Register", "data": {"username": "test"}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+            assert!(responses
+                .iter()
+                .any(|res| res.r#type == "Error" && res.data["code"] == "INCOMPATIBLE_VERSION"));
+            assert!(!responses.iter().any(|res| res.r#type == "Session"));
+            assert!(
+                matches!(
+                    timeout(Duration::from_millis(250), read.next()).await,
+                    Ok(None | Some(Ok(Message::Close(_))))
+                ),
+                "Client without 'Hello' wasn't disconnected"
+            );
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn request_ids_are_echoed() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let (player, _) = connect_async(format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4()))
+                .await
+                .unwrap();
+
+            let (mut write, mut read) = player.split();
+            hello(&mut write).await;
+            read_responses(&mut read).await;
+
+            // Send Request - Register
+            write
+                .send(Message::Text(
+                    r#"{"type": "Register", "data": {"username": "test_1"}, "request_id": "a1"}"#
+                        .to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+            assert!(
+                responses
+                    .iter()
+                    .any(|res| res.r#type == "Ack" && res.request_id.as_deref() == Some("a1")),
+                "Successful request wasn't acknowledged"
+            );
+
+            // Send Request - Start the game alone
+            write
+                .send(Message::Text(
+                    r#"{"type": "StartGame", "data": {}, "request_id": 2}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+            assert!(
+                responses
+                    .iter()
+                    .any(|res| res.r#type == "Error" && res.request_id.as_deref() == Some("2")),
+                "Error didn't carry the request id"
+            );
+            assert!(
+                !responses.iter().any(|res| res.r#type == "Ack"),
+                "Failed request was acknowledged"
+            );
+
+            // Send Request - Packet only sent by the server
+            write
+                .send(Message::Text(
+                    r#"{"type": "Deadlock", "data": null, "request_id": "3"}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+            assert!(
+                responses
+                    .iter()
+                    .any(|res| res.data["code"] == "ILLEGAL_ACTION"
+                        && res.request_id.as_deref() == Some("3")),
+                "Ignored request wasn't rejected"
+            );
+            assert!(
+                !responses.iter().any(|res| res.r#type == "Ack"),
+                "Ignored request was acknowledged"
+            );
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn missed_events_are_replayed() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let url = format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4());
+            let (player, _) = connect_async(&url).await.unwrap();
+
+            let (mut write, mut read) = player.split();
+            hello(&mut write).await;
+            let mut responses = read_responses(&mut read).await;
+
+            // Send Request - Register
+            write
+                .send(Message::Text(
+                    r#"{"type": "Register", "data": {"username": "test_1"}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            responses.extend(read_responses(&mut read).await);
+
+            // Events sent only to the other player don't leave gaps in the stream of the first one
+            let (other, _) = connect_async(&url).await.unwrap();
+            let (mut other_write, mut other_read) = other.split();
+            hello(&mut other_write).await;
+            other_write
+                .send(Message::Text(
+                    r#"{"type": "Register", "data": {"username": "test_2"}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            let other_seqs: Vec<u64> = read_responses(&mut other_read)
+                .await
+                .iter()
+                .map(|res| res.seq)
+                .collect();
+            assert_eq!(
+                other_seqs,
+                (1..=other_seqs.len() as u64).collect::<Vec<u64>>()
+            );
+
+            responses.extend(read_responses(&mut read).await);
+            let seqs: Vec<u64> = responses.iter().map(|res| res.seq).collect();
+            assert_eq!(
+                seqs,
+                (1..=seqs.len() as u64).collect::<Vec<u64>>(),
+                "Events weren't numbered contiguously"
+            );
+            assert!(responses.iter().any(|res| res.r#type == "Connect"));
+
+            // Send Request - Resync from the first event
+            write
+                .send(Message::Text(format!(
+                    r#"{{"type": "Resync", "data": {{"from_seq": {}}}}}"#,
+                    seqs[0]
+                )))
+                .await
+                .unwrap();
+
+            let replayed = read_responses(&mut read).await;
+            assert_eq!(
+                replayed.iter().map(|res| res.seq).collect::<Vec<u64>>(),
+                seqs,
+                "Missed events weren't replayed"
+            );
+
+            // Push the first events out of the history
+            for _ in 0..300 {
+                write
+                    .send(Message::Text(
+                        r#"{"type": "Message", "data": {"sender": "test_1", "content": "spam"}}"#
+                            .to_string(),
+                    ))
+                    .await
+                    .unwrap();
+            }
+            read_responses(&mut read).await;
+
+            // Send Request - Resync from an event that is no longer kept
+            write
+                .send(Message::Text(
+                    r#"{"type": "Resync", "data": {"from_seq": 1}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+            assert!(
+                responses.iter().any(|res| res.r#type == "GameData"),
+                "Server didn't fall back to a snapshot"
+            );
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn messagepack_can_be_negotiated() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let (player, _) = connect_async(format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4()))
+                .await
+                .unwrap();
+
+            let (mut write, mut read) = player.split();
+            read_responses(&mut read).await;
+
+            // Send Request - Hello asking for MessagePack
+            write
+                .send(Message::Text(
+                    r#"{"type": "Hello", "data": {"version": 2, "capabilities": ["msgpack"]}}"#
+                        .to_string(),
+                ))
+                .await
+                .unwrap();
+
+            // Send Request - Register as MessagePack
+            let register = serde_json::json!({"type": "Register", "data": {"username": "test_1"}});
+            write
+                .send(Message::Binary(rmp_serde::to_vec_named(&register).unwrap()))
+                .await
+                .unwrap();
+
+            let mut types = Vec::new();
+            while let Ok(Some(message)) = timeout(Duration::from_millis(250), read.next()).await {
+                match message.unwrap() {
+                    Message::Binary(data) => {
+                        let packet: Value = rmp_serde::from_slice(&data).unwrap();
+                        types.push(packet["type"].as_str().unwrap().to_string());
+                    }
+                    message => panic!("Received a non-binary message: {:?}", message),
+                }
+            }
+
+            assert!(
+                types.iter().any(|t| t == "Welcome"),
+                "Handshake wasn't answered"
+            );
+            assert!(
+                types.iter().any(|t| t == "GameData"),
+                "Binary packet wasn't handled"
+            );
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn rooms_can_be_managed_over_rest() {
+        let lobby = Lobby::default().start();
+        let app = init_service(
+            App::new()
+                .service(create_room)
+                .service(list_rooms)
+                .service(get_room)
+                .service(delete_room)
+                .service(create_invite)
+                .service(start_connection_route)
+                .app_data(Data::new(lobby)),
+        )
+        .await;
+
+        // Create a public room
+        let req = TestRequest::post()
+            .uri("/rooms")
+            .set_json(serde_json::json!({
+                "public": true,
+                "max_players": 4,
+                "options": {"hand_size": 5}
+            }))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let room: Value = read_body_json(res).await;
+        assert_eq!(room["max_players"], 4);
+        assert_eq!(room["options"]["hand_size"], 5);
+        let (id, token) = (
+            room["id"].as_str().unwrap().to_string(),
+            room["token"].as_str().unwrap().to_string(),
+        );
+
+        // Invalid settings are rejected
+        let req = TestRequest::post()
+            .uri("/rooms")
+            .set_json(serde_json::json!({"max_players": 20}))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // Private rooms aren't listed
+        let req = TestRequest::post()
+            .uri("/rooms")
+            .set_json(serde_json::json!({"password": "secret"}))
+            .to_request();
+        let private: Value = read_body_json(call_service(&app, req).await).await;
+        assert_eq!(private["locked"], true);
+
+        let req = TestRequest::get().uri("/rooms").to_request();
+        let rooms: Vec<Value> = read_body_json(call_service(&app, req).await).await;
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0]["id"], id);
+
+        // Invite-only rooms are let in through invites of the creator
+        let req = TestRequest::post()
+            .uri("/rooms")
+            .set_json(serde_json::json!({"invite_only": true}))
+            .to_request();
+        let invite_only: Value = read_body_json(call_service(&app, req).await).await;
+        let req = TestRequest::post()
+            .uri(&format!(
+                "/rooms/{}/invites?token={}",
+                invite_only["id"].as_str().unwrap(),
+                invite_only["token"].as_str().unwrap()
+            ))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let req = TestRequest::get().uri("/rooms?open=true").to_request();
+        let rooms: Vec<Value> = read_body_json(call_service(&app, req).await).await;
+        assert_eq!(rooms.len(), 1);
+
+        let req = TestRequest::get().uri(&format!("/rooms/{id}")).to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Only the creator can delete the room
+        let req = TestRequest::delete()
+            .uri(&format!("/rooms/{id}?token={}", Uuid::new_v4()))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let req = TestRequest::delete()
+            .uri(&format!("/rooms/{id}?token={token}"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        let req = TestRequest::get().uri(&format!("/rooms/{id}")).to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn unused_rooms_expire() {
+        let lobby = Lobby::new(Duration::from_millis(200), Duration::from_secs(60)).start();
+        let app = init_service(
+            App::new()
+                .service(create_room)
+                .service(get_room)
+                .app_data(Data::new(lobby)),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/rooms")
+            .set_json(serde_json::json!({}))
+            .to_request();
+        let room: Value = read_body_json(call_service(&app, req).await).await;
+        let uri = format!("/rooms/{}", room["id"].as_str().unwrap());
+
+        let res = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Nobody connected to the room in time
+        sleep(Duration::from_millis(400)).await;
+        let res = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn locked_rooms_need_the_password() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let room_id = Uuid::new_v4();
+
+            // The first connection creates the room with its password
+            let (owner, _) =
+                connect_async(format!("ws://127.0.0.1:{port}/{room_id}?password=secret"))
+                    .await
+                    .unwrap();
+            let (_, mut owner_read) = owner.split();
+            let responses = read_responses(&mut owner_read).await;
+            assert!(!responses.iter().any(|res| res.r#type == "Error"));
+
+            let (intruder, _) = connect_async(format!("ws://127.0.0.1:{port}/{room_id}"))
+                .await
+                .unwrap();
+            let (_, mut intruder_read) = intruder.split();
+
+            let message = timeout(Duration::from_millis(250), intruder_read.next())
+                .await
+                .unwrap();
+            let response = log_message(message).unwrap();
+            assert_eq!(response.r#type, "Error", "Connected without the password");
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn matchmaking_groups_compatible_players() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let mut clients = Vec::new();
+            for _ in 0..3 {
+                let (client, _) = connect_async(format!("ws://127.0.0.1:{port}/matchmaking"))
+                    .await
+                    .unwrap();
+                clients.push(client.split());
+            }
+
+            // Send Request - Two players want a three player game, one wants a two player game
+            for (i, (write, _)) in clients.iter_mut().enumerate() {
+                let players = if i == 1 { 2 } else { 3 };
+                write
+                    .send(Message::Text(format!(
+                        r#"{{"type": "JoinQueue", "data": {{"players": {}}}}}"#,
+                        players
+                    )))
+                    .await
+                    .unwrap();
+            }
+
+            for (_, read) in clients.iter_mut() {
+                let message = timeout(Duration::from_millis(250), read.next())
+                    .await
+                    .unwrap();
+                let packet: Value =
+                    serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+                assert_eq!(packet["type"], "Queued");
+            }
+
+            // Send Request - A third player for the three player game
+            let (third, _) = connect_async(format!("ws://127.0.0.1:{port}/matchmaking"))
+                .await
+                .unwrap();
+            let (mut third_write, mut third_read) = third.split();
+            third_write
+                .send(Message::Text(
+                    r#"{"type": "JoinQueue", "data": {"players": 3}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+
+            // Skips the queue updates until the player is matched
+            async fn matched<S>(read: &mut S) -> Option<String>
+            where
+                S: Stream<Item = Result<Message, Error>> + Unpin,
+            {
+                while let Ok(Some(message)) = timeout(Duration::from_millis(250), read.next()).await
+                {
+                    let packet: Value =
+                        serde_json::from_str(message.unwrap().to_text().unwrap()).unwrap();
+                    if packet["type"] == "Matched" {
+                        return Some(packet["data"]["room"].to_string());
+                    }
+                }
+                None
+            }
+
+            let room = matched(&mut third_read)
+                .await
+                .expect("Third player wasn't matched");
+            let first = matched(&mut clients[0].1).await;
+            assert_eq!(
+                first.as_ref(),
+                Some(&room),
+                "Players were sent to different rooms"
+            );
+
+            // The player with other preferences keeps waiting
+            assert!(timeout(Duration::from_millis(250), clients[1].1.next())
+                .await
+                .is_err());
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn matchmaking_rejects_options_without_enough_cards(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let (client, _) = connect_async(format!("ws://127.0.0.1:{port}/matchmaking"))
+                .await
+                .unwrap();
+            let (mut write, mut read) = client.split();
+
+            // Send Request - Ten players can't all get twenty cards from a single deck
+            write
+                .send(Message::Text(
+                    r#"{"type": "JoinQueue", "data": {"players": 10, "options": {"hand_size": 20}}}"#
+                        .to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let message = timeout(Duration::from_millis(250), read.next())
+                .await
+                .unwrap();
+            let packet: Value =
+                serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+            assert_eq!(packet["type"], "Error");
+            assert_eq!(packet["data"]["code"], "INVALID_OPTIONS");
+
+            // The player isn't queued
+            assert!(timeout(Duration::from_millis(250), read.next())
+                .await
+                .is_err());
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn invites_can_only_be_used_once() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let room_id = Uuid::new_v4();
+
+            // The first connection creates the room and becomes the host
+            let (host, _) =
+                connect_async(format!("ws://127.0.0.1:{port}/{room_id}?invite_only=true"))
+                    .await
+                    .unwrap();
+            let (mut host_write, mut host_read) = host.split();
+            hello(&mut host_write).await;
+            host_write
+                .send(Message::Text(
+                    r#"{"type": "Register", "data": {"username": "host"}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            read_responses(&mut host_read).await;
+
+            // Send Request - Invite
+            host_write
+                .send(Message::Text(
+                    r#"{"type": "CreateInvite", "data": null}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            let responses = read_responses(&mut host_read).await;
+            let invite = responses
+                .iter()
+                .find(|res| res.r#type == "Invite")
+                .expect("Host didn't receive an invite");
+            let code = invite.data["code"].as_str().unwrap().to_string();
+
+            // Returns the type of the first packet received by a new connection
+            let first_packet = |query: String| async move {
+                let (client, _) = connect_async(format!("ws://127.0.0.1:{port}/{room_id}{query}"))
+                    .await
+                    .unwrap();
+                let (_, mut read) = client.split();
+                let message = timeout(Duration::from_millis(250), read.next())
+                    .await
+                    .unwrap();
+                log_message(message).unwrap().r#type
+            };
+
+            assert_eq!(first_packet(String::new()).await, "Error");
+            assert_ne!(first_packet(format!("?invite={code}")).await, "Error");
+            assert_eq!(
+                first_packet(format!("?invite={code}")).await,
+                "Error",
+                "Invite was used twice"
+            );
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn banned_players_cannot_come_back() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let room_id = Uuid::new_v4();
+            let url = format!("ws://127.0.0.1:{port}/{room_id}");
+
+            let (host, _) = connect_async(&url).await.unwrap();
+            let (mut host_write, mut host_read) = host.split();
+            hello(&mut host_write).await;
+            host_write
+                .send(Message::Text(
+                    r#"{"type": "Register", "data": {"username": "host"}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            read_responses(&mut host_read).await;
+
+            let (guest, _) = connect_async(&url).await.unwrap();
+            let (mut guest_write, mut guest_read) = guest.split();
+            hello(&mut guest_write).await;
+            guest_write
+                .send(Message::Text(
+                    r#"{"type": "Register", "data": {"username": "guest"}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            let responses = read_responses(&mut guest_read).await;
+            let token = responses
+                .iter()
+                .find(|res| res.r#type == "Session")
+                .expect("Guest didn't receive a resume-token")
+                .data["token"]
+                .as_str()
+                .unwrap()
+                .to_string();
+
+            let responses = read_responses(&mut host_read).await;
+            let guest_id = responses
+                .iter()
+                .find(|res| res.r#type == "Connect")
+                .expect("Host wasn't told about the guest")
+                .data["id"]
+                .as_str()
+                .unwrap()
+                .to_string();
+
+            // Send Request - Ban
+            host_write
+                .send(Message::Text(format!(
+                    r#"{{"type": "Ban", "data": {{"id": "{guest_id}"}}}}"#
+                )))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut guest_read).await;
+            assert!(
+                responses.iter().any(|res| res.r#type == "Kicked"),
+                "Guest wasn't told about the ban"
+            );
+            assert!(
+                matches!(
+                    timeout(Duration::from_millis(250), guest_read.next()).await,
+                    Ok(None | Some(Ok(Message::Close(_))))
+                ),
+                "Connection of the guest wasn't closed"
+            );
+
+            let responses = read_responses(&mut host_read).await;
+            assert!(responses.iter().any(|res| res.r#type == "Disconnect"));
+
+            // The session of the guest can't be used to come back
+            let (client, _) = connect_async(format!("{url}?session={token}"))
+                .await
+                .unwrap();
+            let (_, mut read) = client.split();
+            let responses = read_responses(&mut read).await;
+            assert_eq!(responses[0].r#type, "Error");
+            assert_eq!(responses[0].data["code"], "BANNED");
+
+            // Leaving the session out doesn't get around the ban either
+            let (client, _) = connect_async(&url).await.unwrap();
+            let (_, mut read) = client.split();
+            let responses = read_responses(&mut read).await;
+            assert_eq!(responses[0].r#type, "Error");
+            assert_eq!(responses[0].data["code"], "BANNED");
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn held_seats_keep_the_room_open() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) = start_server();
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let url = format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4());
+            let mut tokens = Vec::new();
+            let mut players = Vec::new();
+
+            for name in ["test_1", "test_2"] {
+                let (player, _) = connect_async(&url).await.unwrap();
+                let (mut write, mut read) = player.split();
+                hello(&mut write).await;
+                write
+                    .send(Message::Text(format!(
+                        r#"{{"type": "Register", "data": {{"username": "{name}"}}}}"#
+                    )))
+                    .await
+                    .unwrap();
+                let responses = read_responses(&mut read).await;
+                tokens.push(
+                    responses
+                        .iter()
+                        .find(|res| res.r#type == "Session")
+                        .unwrap()
+                        .data["token"]
+                        .as_str()
+                        .unwrap()
+                        .to_string(),
+                );
+                players.push((write, read));
+            }
+
+            players[0]
+                .0
+                .send(Message::Text(
+                    r#"{"type": "StartGame", "data": {}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            read_responses(&mut players[0].1).await;
+
+            // Only a spectator is left connected, then it leaves as well
+            let (spectator, _) = connect_async(&url).await.unwrap();
+            drop(players);
+            sleep(Duration::from_millis(250)).await;
+            drop(spectator);
+            sleep(Duration::from_millis(250)).await;
+
+            let (player, _) = connect_async(format!("{url}?session={}", tokens[0]))
+                .await
+                .unwrap();
+            let (mut write, mut read) = player.split();
+            hello(&mut write).await;
+            read_responses(&mut read).await;
+            write
+                .send(Message::Text(format!(
+                    r#"{{"type": "Resume", "data": {{"token": "{}"}}}}"#,
+                    tokens[0]
+                )))
+                .await
+                .unwrap();
+
+            let responses = read_responses(&mut read).await;
+            assert!(
+                responses.iter().any(|res| res.r#type == "Snapshot"),
+                "Held seat was lost with the room"
+            );
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn resumed_sessions_restart_the_grace_period() -> Result<(), Box<dyn std::error::Error>> {
+        let (port, server_handle) =
+            start_server_with(Lobby::new(Duration::from_secs(60), Duration::from_secs(1)));
+
+        let client_handle = actix_web::rt::spawn(async move {
+            let url = format!("ws://127.0.0.1:{port}/{}", Uuid::new_v4());
+
+            let (host, _) = connect_async(&url).await.unwrap();
+            let (mut host_write, mut host_read) = host.split();
+            hello(&mut host_write).await;
+            host_write
+                .send(Message::Text(
+                    r#"{"type": "Register", "data": {"username": "host"}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            let responses = read_responses(&mut host_read).await;
+            let token = responses
+                .iter()
+                .find(|res| res.r#type == "Session")
+                .unwrap()
+                .data["token"]
+                .as_str()
+                .unwrap()
+                .to_string();
+
+            let (guest, _) = connect_async(&url).await.unwrap();
+            let (mut guest_write, mut guest_read) = guest.split();
+            hello(&mut guest_write).await;
+            guest_write
+                .send(Message::Text(
+                    r#"{"type": "Register", "data": {"username": "guest"}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            read_responses(&mut guest_read).await;
+
+            host_write
+                .send(Message::Text(
+                    r#"{"type": "StartGame", "data": {}}"#.to_string(),
+                ))
+                .await
+                .unwrap();
+            read_responses(&mut guest_read).await;
+
+            // The host loses connection, resumes and loses connection again
+            drop((host_write, host_read));
+            let (host, _) = connect_async(format!("{url}?session={token}"))
+                .await
+                .unwrap();
+            let (mut host_write, mut host_read) = host.split();
+            hello(&mut host_write).await;
+            host_write
+                .send(Message::Text(format!(
+                    r#"{{"type": "Resume", "data": {{"token": "{token}"}}}}"#
+                )))
+                .await
+                .unwrap();
+            let responses = read_responses(&mut host_read).await;
+            assert!(responses.iter().any(|res| res.r#type == "Snapshot"));
+            sleep(Duration::from_millis(600)).await;
+            drop((host_write, host_read));
+
+            // The grace period of the first disconnect has passed, the second one hasn't
+            sleep(Duration::from_millis(350)).await;
+            let responses = read_responses(&mut guest_read).await;
+            assert!(
+                !responses.iter().any(|res| res.r#type == "Disconnect"),
+                "Host was removed by the timer of an earlier disconnect"
+            );
+
+            sleep(Duration::from_millis(700)).await;
+            let responses = read_responses(&mut guest_read).await;
+            assert!(responses.iter().any(|res| res.r#type == "Disconnect"));
+        });
+
+        client_handle.await?;
+        drop(server_handle);
+        Ok(())
+    }
 }
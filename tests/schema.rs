@@ -0,0 +1,19 @@
+use serde_json::Value;
+use uno_server::packets::schema;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_matches_the_packets() {
+        let expected: Value =
+            serde_json::from_str(include_str!("../schema/protocol.json")).unwrap();
+        let actual = serde_json::to_value(schema()).unwrap();
+
+        assert!(
+            actual == expected,
+            "Packets changed, regenerate the schema with 'cargo run --bin uno-schema > schema/protocol.json'"
+        );
+    }
+}